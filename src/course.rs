@@ -0,0 +1,143 @@
+use std::fs;
+use std::path::Path;
+
+use crate::floor::FloorGenerator;
+use crate::model::{field, Chara, Fnv, Game};
+
+// 手で書いたコース。ランダムな床の代わりに、書いた通りの行を上から順に下から出す
+// 1行にフィールドの1行分を Chara::to_char の文字で並べる。#で始まる行と空行は読み飛ばす
+//   # 針をよけて右へ
+//   .......=====......
+//   ..................
+//   ..........*.......
+//   ..^^^^^...=====...
+// フィールドの幅は最初の行の長さで決まる。最後の行を通り過ぎたらクリア
+#[derive(Debug)]
+pub struct Course {
+    pub path: String,
+    pub id: String, // 記録やゴーストの名前。ファイル名から拡張子を除いたものと中身のハッシュ
+    pub wid: i32,
+    pub rows: Vec<Vec<Chara>>,
+}
+
+impl Course {
+    pub fn load(path: &str) -> Result<Course, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let name = Path::new(path)
+            .file_stem()
+            .map_or(path.to_string(), |stem| stem.to_string_lossy().into_owned());
+        Course::parse(path, &name, &text)
+    }
+
+    fn parse(path: &str, name: &str, text: &str) -> Result<Course, String> {
+        let mut rows: Vec<Vec<Chara>> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let row = line
+                .chars()
+                .map(Chara::from_char)
+                .collect::<Option<Vec<Chara>>>()
+                .ok_or_else(|| format!("{}:{}: invalid row: {}", path, i + 1, line))?;
+            if rows.first().is_some_and(|first| first.len() != row.len()) {
+                return Err(format!("{}:{}: the row width differs", path, i + 1));
+            }
+            rows.push(row);
+        }
+        let wid = rows.first().map_or(0, |row| row.len() as i32);
+        if wid < field::FLOORWID {
+            return Err(format!(
+                "{}: rows must be at least {} cells wide",
+                path,
+                field::FLOORWID
+            ));
+        }
+        Ok(Course {
+            path: path.to_string(),
+            id: format!("{}.{:016x}", name, rows_hash(&rows)),
+            wid,
+            rows,
+        })
+    }
+
+    // このスコアになったらクリア。最後の行がプレイヤーのいる高さ(フィールドの真ん中)を過ぎたとき
+    pub fn goal(&self, hei: i32) -> i32 {
+        self.rows.len() as i32 - 1 + (hei - 1 - hei / 2)
+    }
+}
+
+// 行の中身のハッシュ。同じ名前の別のコースや、書き換えたコースの記録を混ぜない
+fn rows_hash(rows: &[Vec<Chara>]) -> u64 {
+    let mut hash = Fnv::new();
+    for row in rows {
+        let line: String = row.iter().map(|chara| chara.to_char()).collect();
+        hash.add(line.as_bytes());
+        hash.add(b"\n");
+    }
+    hash.finish()
+}
+
+impl FloorGenerator for Course {
+    // コースの床にはランダムなアイテムや敵を乗せない。最後の行の後は何も置かない
+    fn generate(&self, game: &mut Game, row: usize) -> Option<i32> {
+        if let Some(cells) = self.rows.get(row) {
+            let y = (game.hei - 1) as usize;
+            game.data[y] = cells.clone();
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Rules;
+    use std::rc::Rc;
+
+    const TEXT: &str = "\
+# test
+=====.
+......
+..*...
+.^^^^^
+";
+
+    // コースの行が書いた順に一番下へ来て、最後の行の後は何も来ない
+    #[test]
+    fn course_rows_come_in_order() {
+        let course = Course::parse("test.course", "test", TEXT).unwrap();
+        assert_eq!(course.wid, 6);
+        assert_eq!(course.rows.len(), 4);
+        assert!(Course::parse("bad.course", "bad", "=====.\n====\n").is_err());
+        assert!(Course::parse("bad.course", "bad", "==x==.\n").is_err());
+        // 中身が同じならコメントが違っても同じ記録、中身が違えば名前が同じでも別の記録
+        let commented =
+            Course::parse("b/test.course", "test", &TEXT.replace("# test", "")).unwrap();
+        assert_eq!(commented.id, course.id);
+        let changed =
+            Course::parse("test.course", "test", &TEXT.replace("=====.", "....==")).unwrap();
+        assert_ne!(changed.id, course.id);
+
+        let mut rules = Rules::new();
+        rules.wid = course.wid;
+        rules.enemies = true;
+        let mut game = Game::new(rules, 1);
+        let rows = course.rows.clone();
+        let goal = course.goal(rules.hei);
+        game.set_course(Rc::new(course));
+        assert_eq!(game.data[rules.hei as usize - 1], rows[0]);
+        for row in &rows[1..] {
+            game.scroll_field();
+            game.score += 1;
+            assert_eq!(&game.data[rules.hei as usize - 1], row);
+        }
+        game.scroll_field();
+        assert!(game.data[rules.hei as usize - 1]
+            .iter()
+            .all(|c| *c == Chara::EMPTY));
+        assert!(game.enemies.is_empty());
+        assert_eq!(game.goal(), Some(goal));
+    }
+}
@@ -0,0 +1,127 @@
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::lists;
+
+pub const DAILY_FILE: &str = "./daily.cfg";
+
+// その日だけの種で遊ぶデイリーチャレンジ
+// 日付はUTCで決めるので、どこで遊んでも同じ日なら同じ床が出る
+#[derive(Debug, Clone, PartialEq)]
+pub struct Daily {
+    pub date: String, // 2026-10-19
+    pub seed: u64,    // 20261019
+}
+
+impl Daily {
+    pub fn today() -> Daily {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("SystemTime before UNIX EPOCH!")
+            .as_secs();
+        Daily::from_days((secs / 86400) as i64)
+    }
+
+    // 1970-01-01からの日数から年月日を求める
+    fn from_days(days: i64) -> Daily {
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let d = doy - (153 * mp + 2) / 5 + 1;
+        let m = if mp < 10 { mp + 3 } else { mp - 9 };
+        let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+        Daily {
+            date: format!("{:04}-{:02}-{:02}", y, m, d),
+            seed: (y * 10000 + m * 100 + d) as u64,
+        }
+    }
+}
+
+// 日ごとのスコアを挑戦した順に残す。最初の挑戦が公式記録
+//   2026-10-19 = 1200 340 800
+// 途中でやめた回も挑戦に数えるよう、始めたときに0で追加し、終わったときに書き換える
+pub struct DailyScores {
+    days: Vec<(String, Vec<i32>)>,
+}
+
+impl DailyScores {
+    pub fn load(path: &str) -> DailyScores {
+        DailyScores {
+            days: lists::load(path),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        lists::save(path, &self.days)
+    }
+
+    // その日の挑戦のスコア。挑戦した順
+    pub fn attempts(&self, date: &str) -> &[i32] {
+        lists::get(&self.days, date)
+    }
+
+    // 挑戦を始めたときに呼ぶ。最初の挑戦ならtrue
+    pub fn start(&mut self, date: &str) -> bool {
+        match self.days.iter_mut().find(|(d, _)| d == date) {
+            Some((_, values)) => {
+                values.push(0);
+                false
+            }
+            None => {
+                self.days.push((date.to_string(), vec![0]));
+                true
+            }
+        }
+    }
+
+    // 挑戦が終わったときに、最後に始めた挑戦のスコアを書き込む
+    pub fn finish(&mut self, date: &str, score: i32) {
+        if let Some(last) = self
+            .days
+            .iter_mut()
+            .find(|(d, _)| d == date)
+            .and_then(|(_, values)| values.last_mut())
+        {
+            *last = score;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_from_days() {
+        let cases = [
+            (0, "1970-01-01", 19700101),
+            (11016, "2000-02-29", 20000229),
+            (11017, "2000-03-01", 20000301),
+            (20745, "2026-10-19", 20261019),
+            (47846, "2100-12-31", 21001231),
+        ];
+        for (days, date, seed) in cases {
+            let daily = Daily::from_days(days);
+            assert_eq!(daily.date, date);
+            assert_eq!(daily.seed, seed);
+        }
+    }
+
+    // 最初の挑戦だけが公式記録になり、途中でやめた挑戦も数える
+    #[test]
+    fn first_attempt_is_official() {
+        let mut scores = DailyScores { days: Vec::new() };
+        assert!(scores.start("2026-10-19"));
+        scores.finish("2026-10-19", 120);
+        assert!(!scores.start("2026-10-19"));
+        assert!(!scores.start("2026-10-19"));
+        scores.finish("2026-10-19", 300);
+        assert!(scores.start("2026-10-20"));
+        assert_eq!(scores.attempts("2026-10-19"), [120, 0, 300]);
+        assert_eq!(scores.attempts("2026-10-20"), [0]);
+        assert_eq!(scores.attempts("2026-10-21"), [] as [i32; 0]);
+    }
+}
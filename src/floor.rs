@@ -0,0 +1,286 @@
+use crate::model::{field, Chara, Game, HARI_PER_FLOOR};
+
+// 新しく一番下に来る行の作り方。Gameはスクロールするたびにこれで次の行を作る
+pub trait FloorGenerator {
+    // game.dataの一番下の行(空にしてある)に床を、その上の行にアイテムを置く
+    // rowはゲームを始めてから何行目か(最初の行が0)。床を置くのはgame.isfloorの行だけ
+    // 敵を乗せてよい床を置いたときは、その床の幅FLOORWIDの何も乗っていないところの左端を返す
+    fn generate(&self, game: &mut Game, row: usize) -> Option<i32>;
+}
+
+// 床の作り方の種類。モードごとに決まっていて(Mode::floors)、--floorsで変えられる
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Floors {
+    Classic,
+    Dense,
+    Maze,
+    SafePath,
+}
+
+pub const FLOORS: [Floors; 4] = [
+    Floors::Classic,
+    Floors::Dense,
+    Floors::Maze,
+    Floors::SafePath,
+];
+
+impl Floors {
+    // ファイルやコマンドラインで使う名前
+    pub fn name(self) -> &'static str {
+        match self {
+            Floors::Classic => "classic",
+            Floors::Dense => "dense",
+            Floors::Maze => "maze",
+            Floors::SafePath => "safe",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Floors> {
+        FLOORS.iter().copied().find(|floors| floors.name() == name)
+    }
+
+    pub fn generator(self) -> &'static dyn FloorGenerator {
+        match self {
+            Floors::Classic => &Classic,
+            Floors::Dense => &Dense,
+            Floors::Maze => &Maze,
+            Floors::SafePath => &SafePath,
+        }
+    }
+}
+
+// 元のDown!!の床。1行おきに、幅FLOORWIDの床を1つランダムな位置に置く
+// リプレイが同じ結果になるよう、乱数を引く順番も元のままにしている
+pub struct Classic;
+
+impl FloorGenerator for Classic {
+    fn generate(&self, game: &mut Game, row: usize) -> Option<i32> {
+        if !game.isfloor {
+            return None;
+        }
+        let pos = random_pos(game);
+        let _type = random_type(game);
+        put_floor(game, pos, _type);
+
+        // 最初の床にはアイテムも敵も乗せない
+        if row == 0 {
+            return None;
+        }
+        // 針の床でもアイテムの確率は引く
+        if game.rand(100) <= game.mode.item_percent() && _type != Chara::HARI {
+            put_item(game, pos + field::FLOORWID / 2);
+            None
+        } else if _type == Chara::HARI {
+            None
+        } else {
+            Some(pos)
+        }
+    }
+}
+
+// 床の行ごとに床を2つ置く。重なったところは後の床になる
+pub struct Dense;
+
+impl FloorGenerator for Dense {
+    fn generate(&self, game: &mut Game, row: usize) -> Option<i32> {
+        if !game.isfloor {
+            return None;
+        }
+        let mut floors = Vec::new();
+        for _ in 0..2 {
+            let pos = random_pos(game);
+            let _type = random_type(game);
+            put_floor(game, pos, _type);
+            floors.push((pos, _type));
+        }
+        if row == 0 {
+            return None;
+        }
+
+        let mut enemy = None;
+        for (pos, _type) in floors {
+            if _type == Chara::HARI {
+                continue;
+            }
+            if game.rand(100) <= game.mode.item_percent() {
+                put_item(game, pos + field::FLOORWID / 2);
+            } else if enemy.is_none() {
+                enemy = Some(pos);
+            }
+        }
+        // 後の床に一部を針にされた床には敵を乗せない
+        enemy.filter(|&pos| is_bare_block(game, pos))
+    }
+}
+
+// 床の行を端から端まで床で埋め、落ちられる隙間を1つだけ空ける。隙間を探して歩く
+pub struct Maze;
+
+const MAZE_GAP: i32 = 2; // 隙間の幅
+
+impl FloorGenerator for Maze {
+    fn generate(&self, game: &mut Game, row: usize) -> Option<i32> {
+        if !game.isfloor {
+            return None;
+        }
+        let y = (game.hei - 1) as usize;
+        game.data[y] = vec![Chara::BLOCK; game.wid as usize];
+        if game.rand(100) <= HARI_PER_FLOOR {
+            let pos = random_pos(game);
+            put_floor(game, pos, Chara::HARI);
+        }
+        let gap = game.rand(game.wid - MAZE_GAP + 1);
+        for x in gap..gap + MAZE_GAP {
+            game.data[y][x as usize] = Chara::EMPTY;
+        }
+        if row == 0 {
+            return None;
+        }
+
+        if game.rand(100) <= game.mode.item_percent() {
+            let x = game.rand(game.wid);
+            if game.data[y][x as usize] == Chara::BLOCK {
+                put_item(game, x);
+            }
+        }
+        // 隙間や針にかからない床を選んで敵を乗せる
+        let bare: Vec<i32> = (0..=game.wid - field::FLOORWID)
+            .filter(|&pos| is_bare_block(game, pos))
+            .collect();
+        if bare.is_empty() {
+            return None;
+        }
+        Some(bare[game.rand(bare.len() as i32) as usize])
+    }
+}
+
+// 1つ上の床の行の、針でない床の端から落ちたところに、必ず針でない床を置く
+// 針の床はその前に置くので、端から落ちれば必ず安全に着ける。敵も乗せない
+pub struct SafePath;
+
+impl FloorGenerator for SafePath {
+    fn generate(&self, game: &mut Game, row: usize) -> Option<i32> {
+        if !game.isfloor {
+            return None;
+        }
+        let pos = random_pos(game);
+        let _type = random_type(game);
+        put_floor(game, pos, _type);
+
+        let edges = safe_edges(game);
+        let pos = if edges.is_empty() {
+            random_pos(game)
+        } else {
+            let x = edges[game.rand(edges.len() as i32) as usize];
+            (x - game.rand(field::FLOORWID)).clamp(0, game.wid - field::FLOORWID)
+        };
+        put_floor(game, pos, Chara::BLOCK);
+
+        if row > 0 && game.rand(100) <= game.mode.item_percent() {
+            put_item(game, pos + field::FLOORWID / 2);
+        }
+        None
+    }
+}
+
+// 1つ上の床の行(2行上)で、針でない床の隣の何も無いところ。そこへ歩き出せば下へ落ちる
+fn safe_edges(game: &Game) -> Vec<i32> {
+    let above = &game.data[(game.hei - 3) as usize];
+    (0..game.wid)
+        .filter(|&x| {
+            let next_to_block = |dx: i32| {
+                (0..game.wid).contains(&(x + dx)) && above[(x + dx) as usize] == Chara::BLOCK
+            };
+            above[x as usize] == Chara::EMPTY && (next_to_block(-1) || next_to_block(1))
+        })
+        .collect()
+}
+
+// 幅FLOORWIDの床の左端。はみ出す分は端に寄せるので、端に寄った床が出やすい
+fn random_pos(game: &mut Game) -> i32 {
+    let mut pos = game.rand(game.wid + field::FLOORWID) - field::FLOORWID;
+    if pos < 0 {
+        pos = 0;
+    }
+    if pos > game.wid - field::FLOORWID {
+        pos = game.wid - field::FLOORWID;
+    }
+    pos
+}
+
+fn random_type(game: &mut Game) -> Chara {
+    if game.rand(100) <= HARI_PER_FLOOR {
+        // randを<=で比較しているのはバグで、正しくは<だと思う
+        Chara::HARI
+    } else {
+        Chara::BLOCK
+    }
+}
+
+fn put_floor(game: &mut Game, pos: i32, _type: Chara) {
+    for i in 0..field::FLOORWID {
+        game.data[(game.hei - 1) as usize][(pos + i) as usize] = _type;
+    }
+}
+
+// 一番下の行のxの上にランダムなアイテムを置く
+fn put_item(game: &mut Game, x: i32) {
+    let r = game.rand(100);
+    let item_type = if r <= 33 {
+        Chara::STAR
+    } else if r <= 66 {
+        Chara::PARA
+    } else {
+        Chara::OMORI
+    };
+    game.data[(game.hei - 2) as usize][x as usize] = item_type;
+}
+
+// posから幅FLOORWIDが針でない床で、上に何も乗っていない
+fn is_bare_block(game: &Game, pos: i32) -> bool {
+    (pos..pos + field::FLOORWID).all(|x| {
+        game.data[(game.hei - 1) as usize][x as usize] == Chara::BLOCK
+            && game.data[(game.hei - 2) as usize][x as usize] == Chara::EMPTY
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Rules;
+
+    // どの作り方でも床は1行おきに出て、迷路には必ず隙間があり、
+    // 安全な道では1つ上の床の端から落ちたところに針でない床がある
+    #[test]
+    fn generators_keep_their_promises() {
+        for floors in FLOORS {
+            assert_eq!(Floors::from_name(floors.name()), Some(floors));
+            let mut game = Game::new(Rules::new(), 7);
+            game.set_floors(floors);
+            let bottom = (game.hei - 1) as usize;
+            for row in 1..300 {
+                game.scroll_field();
+                game.score += 1;
+                let cells = &game.data[bottom];
+                if row % 2 == 1 {
+                    assert!(cells.iter().all(|c| *c == Chara::EMPTY), "{:?}", floors);
+                    continue;
+                }
+                assert!(cells.iter().any(|c| *c != Chara::EMPTY), "{:?}", floors);
+                match floors {
+                    Floors::Maze => assert!(cells.contains(&Chara::EMPTY)),
+                    Floors::SafePath => {
+                        let edges = safe_edges(&game);
+                        assert!(
+                            edges.is_empty()
+                                || edges.iter().any(|&x| cells[x as usize] == Chara::BLOCK),
+                            "row {}",
+                            row
+                        );
+                    }
+                    Floors::Classic | Floors::Dense => {}
+                }
+            }
+        }
+    }
+}
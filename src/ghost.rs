@@ -0,0 +1,134 @@
+use std::fs;
+use std::io;
+
+use crate::model::{update_games, Game};
+use crate::replay::Replay;
+
+pub const GHOST_DIR: &str = "./ghosts";
+
+// 同じ種で一番スコアの良かった回のリプレイを、今のゲームと並べて進める
+// Gameは種と入力が同じなら同じ結果になるので、リプレイを再生すればその回の動きがそのまま再現できる
+pub struct Ghost {
+    replay: Replay,
+    pub games: Vec<Game>,
+    frame: usize,
+}
+
+impl Ghost {
+    // 種ごとにファイルを分ける。コースは種によらず同じ床なのでコースごとに分ける
+    pub fn path(recording: &Replay) -> String {
+        match &recording.course {
+            Some(course) => format!("{}/course.{}.replay", GHOST_DIR, course.id),
+            None => format!("{}/{}.replay", GHOST_DIR, recording.seed),
+        }
+    }
+
+    pub fn load(recording: &Replay) -> Option<Ghost> {
+        let path = Ghost::path(recording);
+        let replay = Replay::load(&path).ok()?;
+        if !same_setup(&replay, recording) {
+            return None;
+        }
+        Some(Ghost {
+            games: new_games(&replay),
+            replay,
+            frame: 0,
+        })
+    }
+
+    // 今のゲームの経過時間nowに追いつくまで進める。最後まで再生したらそこで止まる
+    // 巻き戻しで今のゲームの方が前に戻ったときは最初から進め直す
+    pub fn advance(&mut self, now: u32) {
+        if self.games[0].now > now {
+            self.games = new_games(&self.replay);
+            self.frame = 0;
+        }
+        while let Some(frame) = self.replay.frames.get(self.frame) {
+            if self.games[0].now + frame.dt > now {
+                break;
+            }
+            update_games(&mut self.games, &frame.commands, frame.dt);
+            self.frame += 1;
+        }
+        for game in &mut self.games {
+            game.requested_sounds.clear();
+            game.requested_musics.clear();
+        }
+    }
+}
+
+fn new_games(replay: &Replay) -> Vec<Game> {
+    (0..replay.games).map(|_| replay.new_game()).collect()
+}
+
+// ルールやゲームの数が違う回のリプレイは並べられないので使わない
+fn same_setup(a: &Replay, b: &Replay) -> bool {
+    let course = |replay: &Replay| replay.course.as_ref().map(|course| course.id.clone());
+    a.rules == b.rules
+        && a.games == b.games
+        && a.versus == b.versus
+        && a.mode == b.mode
+        && a.floors == b.floors
+        && course(a) == course(b)
+}
+
+// 大きいほど良い結果。モードがスコアで競うならスコアの合計、そうでなければ記録で比べる
+fn result(games: &[Game]) -> i64 {
+    let mode = games[0].mode;
+    let score: i64 = games.iter().map(|game| game.score as i64).sum();
+    if mode.ranks_by_score() {
+        return score;
+    }
+    match mode.record(games) {
+        Some(record) if mode.lower_is_better() => -(record as i64),
+        Some(record) => record as i64,
+        None => i64::MIN + score, // タイムアタックでクリアできなかった
+    }
+}
+
+// リプレイを最後まで再生したときの結果
+fn replay_result(replay: &Replay) -> i64 {
+    let mut games = new_games(replay);
+    for frame in &replay.frames {
+        update_games(&mut games, &frame.commands, frame.dt);
+    }
+    result(&games)
+}
+
+// 終わったゲームのリプレイが、同じ種のこれまでのものより良ければ次からのゴーストにする
+pub fn save_if_best(recording: &Replay, games: &[Game]) -> io::Result<bool> {
+    let path = Ghost::path(recording);
+    if let Ok(best) = Replay::load(&path) {
+        if same_setup(&best, recording) && replay_result(&best) >= result(games) {
+            return Ok(false);
+        }
+    }
+    fs::create_dir_all(GHOST_DIR)?;
+    recording.save(&path)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 同じ入力で進めた今のゲームとゴーストは同じ時間に同じ場所にいる
+    #[test]
+    fn ghost_follows_its_replay() {
+        let replay = Replay::load("tests/replays/enemies.replay").unwrap();
+        let mut ghost = Ghost {
+            games: new_games(&replay),
+            replay: replay.clone(),
+            frame: 0,
+        };
+        let mut games = new_games(&replay);
+        for frame in &replay.frames {
+            update_games(&mut games, &frame.commands, frame.dt);
+            ghost.advance(games[0].now);
+            assert_eq!(ghost.games[0].now, games[0].now);
+            assert_eq!(ghost.games[0].hitos[0].x, games[0].hitos[0].x);
+            assert_eq!(ghost.games[0].hitos[0].y, games[0].hitos[0].y);
+        }
+        assert_eq!(replay_result(&replay), result(&games));
+    }
+}
@@ -0,0 +1,379 @@
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::joystick::{HatState, Joystick};
+use sdl2::keyboard::{KeyboardState, Scancode};
+use sdl2::{GameControllerSubsystem, JoystickSubsystem};
+use std::fs;
+use std::io;
+
+use crate::model::Command;
+
+pub const DEADZONE: i16 = 8000; // アナログスティックの遊び（最大32767）
+pub const KEYS_FILE: &str = "./keys.cfg";
+
+// キーに割り当てられる操作
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Action {
+    Left1,
+    Right1,
+    Left2,
+    Right2,
+    Pause,
+    Restart,
+    Quit,
+}
+
+pub const ACTIONS: [Action; 7] = [
+    Action::Left1,
+    Action::Right1,
+    Action::Left2,
+    Action::Right2,
+    Action::Pause,
+    Action::Restart,
+    Action::Quit,
+];
+
+impl Action {
+    // 設定ファイルでの名前
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Left1 => "p1_left",
+            Action::Right1 => "p1_right",
+            Action::Left2 => "p2_left",
+            Action::Right2 => "p2_right",
+            Action::Pause => "pause",
+            Action::Restart => "restart",
+            Action::Quit => "quit",
+        }
+    }
+
+    // 画面に出す名前
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::Left1 => "1P LEFT",
+            Action::Right1 => "1P RIGHT",
+            Action::Left2 => "2P LEFT",
+            Action::Right2 => "2P RIGHT",
+            Action::Pause => "PAUSE",
+            Action::Restart => "RESTART",
+            Action::Quit => "QUIT",
+        }
+    }
+
+    fn default_keys(self) -> Vec<Scancode> {
+        match self {
+            Action::Left1 => vec![Scancode::Left],
+            Action::Right1 => vec![Scancode::Right],
+            Action::Left2 => vec![Scancode::A],
+            Action::Right2 => vec![Scancode::D],
+            Action::Pause => vec![Scancode::P],
+            Action::Restart => vec![Scancode::Space],
+            Action::Quit => vec![Scancode::Escape],
+        }
+    }
+}
+
+// 操作ごとのキー割り当て。1つの操作に複数のキーを割り当てられる
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    keys: Vec<Vec<Scancode>>, // ACTIONSと同じ順
+}
+
+impl KeyBindings {
+    pub fn new() -> KeyBindings {
+        KeyBindings {
+            keys: ACTIONS.iter().map(|action| action.default_keys()).collect(),
+        }
+    }
+
+    // 設定ファイルが無ければ初期設定のまま
+    //   p1_left = Left, H
+    pub fn load(path: &str) -> KeyBindings {
+        let mut bindings = KeyBindings::new();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return bindings,
+        };
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => {
+                    println!("Invalid line in {}: {}", path, line);
+                    continue;
+                }
+            };
+            let action = match ACTIONS.iter().find(|action| action.name() == name) {
+                Some(&action) => action,
+                None => {
+                    println!("Unknown action in {}: {}", path, name);
+                    continue;
+                }
+            };
+            let mut keys = Vec::new();
+            for key_name in value.split(',').map(|key_name| key_name.trim()) {
+                match Scancode::from_name(key_name) {
+                    Some(key) => keys.push(key),
+                    None => println!("Unknown key in {}: {}", path, key_name),
+                }
+            }
+            // 使えるキーが1つも無ければ操作できなくなるので、初期のキーのままにする
+            if keys.is_empty() {
+                println!("No valid keys for {} in {}", name, path);
+                continue;
+            }
+            bindings.set(action, keys);
+        }
+        bindings
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut text = String::new();
+        for action in ACTIONS {
+            text += &format!("{} = {}\n", action.name(), self.key_names(action));
+        }
+        fs::write(path, text)
+    }
+
+    pub fn keys(&self, action: Action) -> &[Scancode] {
+        &self.keys[action as usize]
+    }
+
+    pub fn set(&mut self, action: Action, keys: Vec<Scancode>) {
+        self.keys[action as usize] = keys;
+    }
+
+    pub fn key_names(&self, action: Action) -> String {
+        self.keys(action)
+            .iter()
+            .map(|key| key.name())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    }
+
+    pub fn action_of(&self, key: Scancode) -> Option<Action> {
+        ACTIONS
+            .iter()
+            .copied()
+            .find(|action| self.keys(*action).contains(&key))
+    }
+
+    pub fn command(&self, keyboard_state: &KeyboardState, player: usize) -> Command {
+        let (left, right) = match player {
+            0 => (Action::Left1, Action::Right1),
+            1 => (Action::Left2, Action::Right2),
+            _ => return Command::None,
+        };
+        let is_pressed = |action| {
+            self.keys(action)
+                .iter()
+                .any(|key| keyboard_state.is_scancode_pressed(*key))
+        };
+        if is_pressed(left) {
+            Command::Left
+        } else if is_pressed(right) {
+            Command::Right
+        } else {
+            Command::None
+        }
+    }
+}
+
+// キー割り当てを変更する画面の状態
+pub struct Remap {
+    pub bindings: KeyBindings,
+    pub index: usize,           // 入力待ちの操作
+    pub pressed: Vec<Scancode>, // 入力待ちの操作に押されたキー
+}
+
+impl Remap {
+    pub fn new(bindings: &KeyBindings) -> Remap {
+        Remap {
+            bindings: bindings.clone(),
+            index: 0,
+            pressed: Vec::new(),
+        }
+    }
+
+    // Enterで次の操作へ進む。何も押さずにEnterなら今の割り当てのまま
+    // 全部終わったらtrueを返す
+    pub fn press(&mut self, key: Scancode) -> bool {
+        if key == Scancode::Return {
+            if !self.pressed.is_empty() {
+                let keys = std::mem::take(&mut self.pressed);
+                self.bindings.set(ACTIONS[self.index], keys);
+            }
+            self.index += 1;
+            return self.index >= ACTIONS.len();
+        }
+        if !self.pressed.contains(&key) {
+            self.pressed.push(key);
+        }
+        false
+    }
+}
+
+// ゲームパッドのボタンで行う操作
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PadAction {
+    Restart,
+    Pause,
+}
+
+// GameControllerとして認識できないジョイスティックはそのまま使う
+enum Pad {
+    Controller(GameController),
+    Joystick(Joystick),
+}
+
+impl Pad {
+    fn instance_id(&self) -> u32 {
+        match self {
+            Pad::Controller(controller) => controller.instance_id(),
+            Pad::Joystick(joystick) => joystick.instance_id(),
+        }
+    }
+
+    fn command(&self) -> Command {
+        let (left, right, x) = match self {
+            Pad::Controller(controller) => (
+                controller.button(Button::DPadLeft),
+                controller.button(Button::DPadRight),
+                controller.axis(Axis::LeftX),
+            ),
+            Pad::Joystick(joystick) => {
+                let hat = if joystick.num_hats() > 0 {
+                    joystick.hat(0).unwrap_or(HatState::Centered)
+                } else {
+                    HatState::Centered
+                };
+                let x = if joystick.num_axes() > 0 {
+                    joystick.axis(0).unwrap_or(0)
+                } else {
+                    0
+                };
+                (
+                    matches!(hat, HatState::Left | HatState::LeftUp | HatState::LeftDown),
+                    matches!(
+                        hat,
+                        HatState::Right | HatState::RightUp | HatState::RightDown
+                    ),
+                    x,
+                )
+            }
+        };
+        if left || x < -DEADZONE {
+            Command::Left
+        } else if right || x > DEADZONE {
+            Command::Right
+        } else {
+            Command::None
+        }
+    }
+}
+
+// 接続されたパッドを先着順にプレイヤーへ割り当てる
+pub struct Gamepads {
+    controller_subsystem: GameControllerSubsystem,
+    joystick_subsystem: JoystickSubsystem,
+    slots: Vec<Option<Pad>>,
+}
+
+impl Gamepads {
+    pub fn new(
+        controller_subsystem: GameControllerSubsystem,
+        joystick_subsystem: JoystickSubsystem,
+        players: usize,
+    ) -> Gamepads {
+        Gamepads {
+            controller_subsystem,
+            joystick_subsystem,
+            slots: (0..players).map(|_| None).collect(),
+        }
+    }
+
+    // 抜き差しを処理し、ボタンが押されたらその操作と押したプレイヤーを返す
+    pub fn handle_event(&mut self, event: &Event) -> Option<(usize, PadAction)> {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                match self.controller_subsystem.open(which) {
+                    Ok(controller) => {
+                        println!("Controller connected: {}", controller.name());
+                        self.assign(Pad::Controller(controller));
+                    }
+                    Err(e) => println!("cannot open controller: {}", e),
+                }
+                None
+            }
+            Event::JoyDeviceAdded { which, .. } => {
+                // GameControllerとして扱えるものはControllerDeviceAddedで開く
+                if self.controller_subsystem.is_game_controller(which) {
+                    return None;
+                }
+                match self.joystick_subsystem.open(which) {
+                    Ok(joystick) => {
+                        println!("Joystick connected: {}", joystick.name());
+                        self.assign(Pad::Joystick(joystick));
+                    }
+                    Err(e) => println!("cannot open joystick: {}", e),
+                }
+                None
+            }
+            Event::ControllerDeviceRemoved { which, .. }
+            | Event::JoyDeviceRemoved { which, .. } => {
+                for slot in &mut self.slots {
+                    if slot.as_ref().map(|pad| pad.instance_id()) == Some(which) {
+                        *slot = None;
+                    }
+                }
+                None
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                let action = match button {
+                    Button::A => PadAction::Restart,
+                    Button::Start => PadAction::Pause,
+                    _ => return None,
+                };
+                self.player_of(which).map(|player| (player, action))
+            }
+            Event::JoyButtonDown {
+                which, button_idx, ..
+            } => {
+                let action = match button_idx {
+                    0 => PadAction::Restart,
+                    1 => PadAction::Pause,
+                    _ => return None,
+                };
+                // GameControllerを開くと中のジョイスティックも開かれ、同じボタンでこのイベントも来る
+                // ControllerButtonDownで処理済みなので、ジョイスティックとして開いたものだけを見る
+                self.player_of(which)
+                    .filter(|&player| matches!(self.slots[player], Some(Pad::Joystick(_))))
+                    .map(|player| (player, action))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn command(&self, player: usize) -> Command {
+        match self.slots.get(player) {
+            Some(Some(pad)) => pad.command(),
+            _ => Command::None,
+        }
+    }
+
+    fn assign(&mut self, pad: Pad) {
+        match self.slots.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some(pad),
+            None => println!("No free player for the new pad"),
+        }
+    }
+
+    fn player_of(&self, instance_id: u32) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| slot.as_ref().map(|pad| pad.instance_id()) == Some(instance_id))
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+
+// 1行に名前と値の並びを書くファイル。モードごとの記録や日ごとのスコアに使う
+//   timeattack = 83450 90210
+// ファイルが無ければ空。読めない行は知らせて飛ばす
+pub fn load<T: FromStr>(path: &str) -> Vec<(String, Vec<T>)> {
+    let mut lists = Vec::new();
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return lists,
+    };
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_line(line) {
+            Some(list) => lists.push(list),
+            None => println!("Invalid line in {}: {}", path, line),
+        }
+    }
+    lists
+}
+
+fn parse_line<T: FromStr>(line: &str) -> Option<(String, Vec<T>)> {
+    let (name, values) = line.split_once('=')?;
+    let values = values
+        .split_whitespace()
+        .map(|value| value.parse().ok())
+        .collect::<Option<Vec<T>>>()?;
+    Some((name.trim().to_string(), values))
+}
+
+pub fn save<T: Display>(path: &str, lists: &[(String, Vec<T>)]) -> io::Result<()> {
+    let text: String = lists
+        .iter()
+        .map(|(name, values)| {
+            let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+            format!("{} = {}\n", name, values.join(" "))
+        })
+        .collect();
+    fs::write(path, text)
+}
+
+// 名前の値の並び。無ければ空
+pub fn get<'a, T>(lists: &'a [(String, Vec<T>)], name: &str) -> &'a [T] {
+    lists
+        .iter()
+        .find(|(n, _)| n == name)
+        .map_or(&[], |(_, values)| values.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_are_saved_and_loaded() {
+        let lists = vec![
+            ("2026-10-19".to_string(), vec![1200, -1, 0]),
+            ("course.abc".to_string(), vec![]),
+        ];
+        let path = std::env::temp_dir().join("rust-down-test.lists");
+        let path = path.to_str().unwrap();
+        save(path, &lists).unwrap();
+        let loaded: Vec<(String, Vec<i32>)> = load(path);
+        fs::remove_file(path).unwrap();
+        assert_eq!(loaded, lists);
+        assert_eq!(get(&loaded, "2026-10-19"), [1200, -1, 0]);
+        assert_eq!(get(&loaded, "none"), [] as [i32; 0]);
+        assert_eq!(parse_line::<u32>("timeattack = 1 x"), None);
+        assert_eq!(parse_line::<u32>("timeattack 1"), None);
+    }
+}
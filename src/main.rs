@@ -1,19 +1,51 @@
 use field;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Mod, Scancode};
 use sdl2::mixer;
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
+use sdl2::render::{BlendMode, Canvas, RenderTarget, Texture, TextureCreator};
+use sdl2::surface::Surface;
 use sdl2::ttf::Sdl2TtfContext;
-use sdl2::video::{Window, WindowContext};
-use std::collections::HashMap;
+use sdl2::video::{FullscreenType, Window, WindowContext};
+use std::collections::{HashMap, VecDeque};
 use std::fs;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+mod course;
+mod daily;
+mod floor;
+mod ghost;
+mod input;
+mod lists;
+mod mode;
 mod model;
+mod recorder;
+mod records;
+mod replay;
+mod rewind;
+mod settings;
+mod snapshot;
+use crate::course::Course;
+use crate::daily::{Daily, DailyScores, DAILY_FILE};
+use crate::floor::Floors;
+use crate::ghost::Ghost;
+use crate::input::{Action, Gamepads, KeyBindings, PadAction, Remap, ACTIONS, KEYS_FILE};
+use crate::mode::{time_text, Mode, MODES};
 use crate::model::*;
+use crate::recorder::Recorder;
+use crate::records::{Records, RECORDS_FILE};
+use crate::replay::{Replay, REPLAY_FILE};
+use crate::rewind::Rewind;
+use crate::settings::{Item, RulePreset, Settings, ITEMS, SETTINGS_FILE};
+use crate::snapshot::{Snapshot, SNAPSHOT_FILE, SUSPEND_FILE};
 
 pub const SCREEN_W: i32 = 640;
 pub const SCREEN_H: i32 = 480;
+pub const PLAYER_COLORS: [(u8, u8, u8); 2] = [(255, 255, 255), (128, 200, 255)];
+pub const FRAME_GRAPH_LEN: usize = 120; // フレーム時間グラフに出すフレーム数
+pub const TEXT_CACHE_FRAMES: u32 = 60; // 使われなくなった文字列のテクスチャを捨てるまでのフレーム数
+pub const HUD_W: i32 = 320; // 右側のスコア表示欄の幅。元の画面でフィールドの右に残っていた幅
 
 mod sound {
     pub const MAX_CHANNELS: i32 = 10;
@@ -41,113 +73,1054 @@ impl<'a> Image<'a> {
     }
 }
 
-struct Resources<'a> {
+// フィールドの大きさから決まる画面上の配置
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    wid: i32,
+    hei: i32,
+    left: i32,
+    top: i32,
+    right: i32,
+    pane_x: i32,
+    pane_w: i32,
+    hud_x: i32, // スコア欄の文字の左端
+    screen_h: i32,
+}
+
+impl Layout {
+    // 2人プレイでは画面を横に並べる。paneは左から何番目か
+    fn new(wid: i32, hei: i32, pane: i32) -> Layout {
+        let pane_w = Layout::pane_w(wid);
+        let left = pane_w * pane + field::LEFT;
+        let right = left + (CHAR * wid) - 1; // -1している理由不明
+        Layout {
+            wid,
+            hei,
+            left,
+            top: field::TOP,
+            right,
+            pane_x: pane_w * pane,
+            pane_w,
+            hud_x: right + 32,
+            screen_h: std::cmp::max(SCREEN_H, field::TOP + CHAR * hei),
+        }
+    }
+
+    fn pane_w(wid: i32) -> i32 {
+        field::LEFT + (CHAR * wid) + CHAR + HUD_W
+    }
+
+    // スコア欄の下半分の高さ。元の画面の高さ(SCREEN_H)でのyを、フィールドの高さに合わせて伸ばす
+    fn hud_y(&self, y: i32) -> i32 {
+        y * self.screen_h / SCREEN_H
+    }
+}
+
+// 遊び方によって変わるスコア欄の表示
+#[derive(Default)]
+struct Hud {
+    label: Option<String>,                        // 遊んでいるモード
+    ranking: Option<(&'static str, Vec<String>)>, // 見出しと行。Noneならゲームのハイスコア
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Scene {
+    Title,
+    Settings,
+    Play,
+}
+
+struct Options {
+    rules: Rules,
+    rule_args: Vec<String>, // ルールを変えるコマンドラインの指定。設定画面でルールを選び直しても残す
+    rules_fixed: bool,      // リプレイや保存した状態のルールを使うので、設定画面で変えない
+    players: usize,
+    versus: bool,
+    practice: bool,    // 巻き戻しができる代わりにハイスコアに載らない
+    seed: Option<u64>, // 無ければ起動時刻から作る
+    daily: bool,       // デイリーチャレンジ
+    mode: Mode,
+    course: Option<Rc<Course>>,   // Mode::Courseで遊ぶコース
+    floors: Option<Floors>,       // 無ければモードで決まる床の作り方
+    replay: Option<Replay>,       // 再生するリプレイ
+    snapshot: Option<Snapshot>,   // 保存した状態から始める
+    screenshot_at: Option<usize>, // このフレームを描いたらスクリーンショットを撮る
+    headless: bool,               // ウィンドウを開かずにリプレイを画像にする
+    record: bool,                 // headlessのとき、1枚でなく連番の画像に書き出す
+}
+
+impl Options {
+    // ルールは設定画面で選んだものに、コマンドラインの指定を上書きする
+    // リプレイを再生するときはリプレイのルールを使う
+    fn parse(
+        mut args: impl Iterator<Item = String>,
+        preset: RulePreset,
+    ) -> Result<Options, String> {
+        let mut options = Options {
+            rules: Rules::new(),
+            rule_args: Vec::new(),
+            rules_fixed: false,
+            players: 1,
+            versus: false,
+            practice: false,
+            seed: None,
+            daily: false,
+            mode: Mode::Endless,
+            course: None,
+            floors: None,
+            replay: None,
+            snapshot: None,
+            screenshot_at: None,
+            headless: false,
+            record: false,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                // ルールは設定画面のルールと合わせて最後にまとめて決める
+                _ if apply_rule_arg(&mut options.rules, &arg) => {
+                    options.versus |= arg == "--versus";
+                    options.rule_args.push(arg);
+                }
+                "--race" => options.players = 2,
+                "--practice" => options.practice = true,
+                "--daily" => options.daily = true,
+                "--time-attack" => options.mode = Mode::TimeAttack,
+                "--survival" => options.mode = Mode::Survival,
+                "--floors" => {
+                    let name = args.next().ok_or("--floors needs a name")?;
+                    let floors =
+                        Floors::from_name(&name).ok_or(format!("unknown floors: {}", name))?;
+                    options.floors = Some(floors);
+                }
+                "--course" => {
+                    let path = args.next().ok_or("--course needs a file")?;
+                    options.course = Some(Rc::new(Course::load(&path)?));
+                    options.mode = Mode::Course;
+                }
+                "--seed" => {
+                    let seed = args.next().ok_or("--seed needs a number")?;
+                    options.seed = Some(seed.parse().map_err(|_| "invalid seed".to_string())?);
+                }
+                "--replay" => {
+                    let path = args.next().ok_or("--replay needs a file")?;
+                    options.replay = Some(Replay::load(&path)?);
+                }
+                "--snapshot" => {
+                    let path = args.next().ok_or("--snapshot needs a file")?;
+                    let mut snapshot = snapshot::load(&path)?;
+                    snapshot.unrank();
+                    options.snapshot = Some(snapshot);
+                }
+                "--screenshot-at" => {
+                    let frame = args.next().ok_or("--screenshot-at needs a frame")?;
+                    options.screenshot_at =
+                        Some(frame.parse().map_err(|_| "invalid frame".to_string())?);
+                }
+                "--headless" => options.headless = true,
+                "--record" => options.record = true,
+                _ => println!("Unknown option: {}", arg),
+            }
+        }
+        options.apply_preset(preset);
+        // 操作を割り当てられるのは2人まで
+        if options.players > 1 && options.rules.players > 1 {
+            return Err("--race cannot be combined with --coop or --versus".to_string());
+        }
+        // デイリーチャレンジは日付で種が決まり、最初の1回だけが記録になる
+        if options.daily && options.practice {
+            return Err("--daily cannot be combined with --practice".to_string());
+        }
+        if options.daily && options.seed.is_some() {
+            return Err("--daily cannot be combined with --seed".to_string());
+        }
+        if options.daily && options.mode != Mode::Endless {
+            return Err(
+                "--daily cannot be combined with --time-attack, --survival or --course".to_string(),
+            );
+        }
+        if options.record && !options.headless {
+            return Err("--record needs --headless".to_string());
+        }
+        if let Some(replay) = &options.replay {
+            options.rules = replay.rules;
+            options.players = replay.games;
+            options.versus = replay.versus;
+            options.practice = false;
+            options.daily = false;
+            options.mode = replay.mode;
+            options.course = replay.course.clone();
+            options.floors = Some(replay.floors);
+            options.rules_fixed = true;
+        }
+        if let Some(snapshot) = &options.snapshot {
+            let game = &snapshot.games[0];
+            options.rules = game.rules;
+            options.players = snapshot.games.len();
+            options.versus = snapshot.versus;
+            options.course = game.course.clone();
+            options.floors = Some(game.floors);
+            options.rules_fixed = true;
+        }
+        Ok(options)
+    }
+
+    // 設定画面で選んだルールに、コマンドラインの指定を上書きする
+    fn apply_preset(&mut self, preset: RulePreset) {
+        if self.rules_fixed {
+            return;
+        }
+        preset.apply(&mut self.rules);
+        for arg in &self.rule_args {
+            apply_rule_arg(&mut self.rules, arg);
+        }
+        // フィールドの幅はコースに合わせる
+        if let Some(course) = &self.course {
+            self.rules.wid = course.wid;
+        }
+    }
+}
+
+// ルールを変えるコマンドラインの指定ならrulesに反映してtrueを返す
+fn apply_rule_arg(rules: &mut Rules, arg: &str) -> bool {
+    match arg {
+        "--ceiling" => rules.ceiling = true,
+        "--enemies" => rules.enemies = true,
+        "--no-enemies" => rules.enemies = false,
+        "--narrow" => rules.wid = 10,
+        "--wide" => rules.wid = 30,
+        "--tall" => rules.hei = 40,
+        "--coop" | "--versus" => rules.players = 2,
+        _ => return false,
+    }
+    true
+}
+
+// Cはテクスチャを作る描画先の種類。ウィンドウならWindowContext、画像ならSurfaceContext
+struct Resources<'a, C> {
     images: HashMap<String, Image<'a>>,
     chunks: HashMap<String, sdl2::mixer::Chunk>,
     fonts: HashMap<String, sdl2::ttf::Font<'a, 'a>>,
+    texts: TextCache<'a, C>,
+}
+
+// 文字列を描いたテクスチャを使い回す
+// 毎フレーム同じ文字列をラスタライズし直さないようにし、しばらく使われなかったものは捨てる
+struct TextCache<'a, C> {
+    texture_creator: &'a TextureCreator<C>,
+    textures: HashMap<(String, Color), (Texture<'a>, u32)>, // 最後に使ったフレーム
+    frame: u32,
+}
+
+impl<'a, C> TextCache<'a, C> {
+    fn new(texture_creator: &'a TextureCreator<C>) -> TextCache<'a, C> {
+        TextCache {
+            texture_creator,
+            textures: HashMap::new(),
+            frame: 0,
+        }
+    }
+
+    fn get(&mut self, font: &sdl2::ttf::Font, text: String, color: Color) -> &Texture<'a> {
+        let frame = self.frame;
+        let texture_creator = self.texture_creator;
+        let entry = self
+            .textures
+            .entry((text, color))
+            .or_insert_with_key(|(text, color)| {
+                let surface = font.render(text).blended(*color).unwrap();
+                let texture = texture_creator
+                    .create_texture_from_surface(&surface)
+                    .unwrap();
+                (texture, frame)
+            });
+        entry.1 = frame;
+        &entry.0
+    }
+
+    // 1フレームに1回呼ぶ
+    fn sweep(&mut self) {
+        // 長く動かしても止まらないよう、回り込んでも差が正しく出るようにする
+        self.frame = self.frame.wrapping_add(1);
+        let frame = self.frame;
+        self.textures
+            .retain(|_, (_, used)| frame.wrapping_sub(*used) < TEXT_CACHE_FRAMES);
+    }
 }
 
 pub fn main() -> Result<(), String> {
+    let mut settings = Settings::load(SETTINGS_FILE);
+    let mut options = Options::parse(std::env::args().skip(1), settings.preset)?;
+    if options.headless {
+        return run_headless(&options, &settings);
+    }
+
     let sdl_context = sdl2::init()?;
 
+    let (screen_w, screen_h) = screen_size(&options);
+
     let video_subsystem = sdl_context.video()?;
     let window = video_subsystem
-        .window("rust-down", SCREEN_W as u32, SCREEN_H as u32)
+        .window("rust-down", screen_w as u32, screen_h as u32)
         .position_centered()
         .opengl()
+        .resizable()
+        .allow_highdpi()
         .build()
         .map_err(|e| e.to_string())?;
 
     sdl_context.mouse().show_cursor(false);
 
-    init_mixer();
+    init_mixer(&settings);
 
     let music = sdl2::mixer::Music::from_file("./resources/sound/dark3.it")?;
 
     let timer = sdl_context.timer()?;
     let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
 
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let mut canvas_builder = window.into_canvas();
+    if settings.vsync {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let mut canvas = canvas_builder.build().map_err(|e| e.to_string())?;
     canvas.set_blend_mode(BlendMode::Blend);
+    // 描画は論理サイズの座標で行い、ウィンドウには整数倍に拡大して余白を黒で埋める
+    canvas
+        .set_logical_size(screen_w as u32, screen_h as u32)
+        .map_err(|e| e.to_string())?;
+    canvas.set_integer_scale(true)?;
+    canvas
+        .window_mut()
+        .set_minimum_size(screen_w as u32, screen_h as u32)
+        .map_err(|e| e.to_string())?;
 
     let texture_creator = canvas.texture_creator();
-    let mut resources = load_resources(&texture_creator, &mut canvas, &ttf_context);
+    let mut resources = load_resources(&texture_creator, &ttf_context, true);
 
     let mut event_pump = sdl_context.event_pump()?;
 
-    let mut game = Game::new();
+    let players = options.players * options.rules.players;
+    let mut gamepads = Gamepads::new(
+        sdl_context.game_controller()?,
+        sdl_context.joystick()?,
+        players,
+    );
+
+    let mut bindings = KeyBindings::load(KEYS_FILE);
+    let mut remap: Option<Remap> = None;
+    let mut remap_paused = false; // キー設定を開く前にポーズしていたか。閉じたら戻す
+
+    apply_volume(&settings);
+    apply_window(&settings, &mut canvas)?;
+
+    // リプレイの再生や保存した状態からの再開、モードの指定があるときはタイトルを飛ばしてすぐ始める
+    let mut resume = options.snapshot.take(); // 次に始めるときに新しいゲームの代わりに使う
+    let mut scene = if options.replay.is_some()
+        || resume.is_some()
+        || options.practice
+        || options.daily
+        || options.mode != Mode::Endless
+    {
+        Scene::Play
+    } else {
+        Scene::Title
+    };
+    let mut autostart = scene == Scene::Play;
+    let mut cursor = 0; // タイトルと設定画面で選んでいる項目
+    let mut games = new_games(&options, time_seed());
+    let mut paused = false;
+    // 途中から再開したゲームは最初からの入力が無いので記録しない
+    let mut recording = None;
+    let mut practice = options.practice;
+    let mut daily_mode = options.daily;
+    let mut daily: Option<Daily> = None; // 遊んでいるデイリーチャレンジの日
+    let mut daily_scores = DailyScores::load(DAILY_FILE);
+    let mut official = false; // その日の最初の挑戦
+    let mut mode = options.mode;
+    let mut records = Records::load(RECORDS_FILE);
+    // 通常のランキング。デイリーチャレンジの間はその日の表を出すので分けて持つ
+    let mut rankings: Vec<Vec<i32>> = vec![Vec::new(); options.players];
+    let mut rewind = Rewind::new();
+    let mut ghost: Option<Ghost> = None;
+    let mut frame = 0; // ゲーム開始からのフレーム数
 
     println!("Keys:");
-    println!("  Left, Right : Move player");
-    println!("  Space       : Restart when game over");
+    for action in ACTIONS {
+        println!("  {:8} : {}", action.label(), bindings.key_names(action));
+    }
+    println!("  F1       : Change key bindings");
+    println!("  F11, Alt+Enter : Toggle fullscreen");
+    println!("  F3       : Show frame time graph");
+    println!("  F12      : Save screenshot");
+    println!("  F5       : Quick save");
+    println!("  F8       : Quick load");
+    println!("  Backspace: Rewind (hold, practice mode)");
+    println!("Gamepad:");
+    println!("  D-pad, Stick: Move player");
+    println!("  Start       : Pause");
+    println!("  A           : Start, Restart when game over");
 
     let mut before;
     let mut now = timer.ticks();
-
-    music.play(-1)?;
+    let mut frame_graph = FrameGraph::new();
+    let mut frame_start = Instant::now();
 
     'running: loop {
-        let mut command = Command::None;
+        frame_graph.push(frame_start.elapsed());
+        frame_start = Instant::now();
+
         let keyboard_state = event_pump.keyboard_state();
-        if keyboard_state.is_scancode_pressed(sdl2::keyboard::Scancode::Left) {
-            command = Command::Left;
-        } else if keyboard_state.is_scancode_pressed(sdl2::keyboard::Scancode::Right) {
-            command = Command::Right;
-        }
+        // キーボードが押されていなければゲームパッドを見る
+        let commands: Vec<Command> = (0..players)
+            .map(|i| match bindings.command(&keyboard_state, i) {
+                Command::None => gamepads.command(i),
+                command => command,
+            })
+            .collect();
+        let rewind_key = keyboard_state.is_scancode_pressed(Scancode::Backspace);
+
+        let mut restart = false;
+        let mut start = std::mem::take(&mut autostart);
+        let mut screenshot = false;
         for event in event_pump.poll_iter() {
-            match event {
+            match gamepads.handle_event(&event) {
+                Some((_, PadAction::Restart)) => restart = true,
+                Some((_, PadAction::Pause)) => paused = !paused,
+                None => {}
+            }
+            let (key, keymod) = match event {
                 Event::Quit { .. } => break 'running,
                 Event::KeyDown {
-                    keycode: Some(code),
+                    scancode: Some(key),
+                    keymod,
+                    repeat: false,
                     ..
-                } => {
-                    if code == Keycode::Escape {
-                        break 'running;
+                } => (key, keymod),
+                _ => continue,
+            };
+            if key == Scancode::F11
+                || (key == Scancode::Return && keymod.intersects(Mod::LALTMOD | Mod::RALTMOD))
+            {
+                settings.fullscreen = !settings.fullscreen;
+                apply_window(&settings, &mut canvas)?;
+                continue;
+            }
+            if key == Scancode::F3 {
+                frame_graph.visible = !frame_graph.visible;
+                continue;
+            }
+            if key == Scancode::F12 {
+                screenshot = true;
+                continue;
+            }
+            if let Some(state) = &mut remap {
+                if key == Scancode::Escape {
+                    remap = None;
+                    paused = remap_paused;
+                } else if state.press(key) {
+                    bindings = state.bindings.clone();
+                    if let Err(e) = bindings.save(KEYS_FILE) {
+                        println!("cannot save {}: {}", KEYS_FILE, e);
                     }
-                    if game.is_over && game.hito.hide && code == Keycode::Space {
-                        let old_highscore = game.highscore;
-                        game = Game::new();
-                        game.highscore = old_highscore;
-                        music.play(-1)?;
+                    remap = None;
+                    paused = remap_paused;
+                }
+                continue;
+            }
+            match scene {
+                Scene::Title => match key {
+                    Scancode::Up => {
+                        cursor = (cursor + title_items().len() - 1) % title_items().len()
+                    }
+                    Scancode::Down => cursor = (cursor + 1) % title_items().len(),
+                    Scancode::Return => start = true,
+                    _ => match bindings.action_of(key) {
+                        Some(Action::Quit) => break 'running,
+                        Some(Action::Restart) => start = true,
+                        _ => {}
+                    },
+                },
+                Scene::Settings => {
+                    let item = ITEMS[cursor];
+                    match key {
+                        Scancode::Up => cursor = (cursor + ITEMS.len() - 1) % ITEMS.len(),
+                        Scancode::Down => cursor = (cursor + 1) % ITEMS.len(),
+                        Scancode::Left | Scancode::Right => {
+                            item.change(&mut settings, if key == Scancode::Left { -1 } else { 1 });
+                            options.apply_preset(settings.preset);
+                            apply_volume(&settings);
+                            if item == Item::Scale || item == Item::Fullscreen {
+                                apply_window(&settings, &mut canvas)?;
+                            }
+                        }
+                        Scancode::Return if item == Item::KeyConfig => {
+                            remap = Some(Remap::new(&bindings));
+                            remap_paused = paused;
+                        }
+                        Scancode::Return | Scancode::Escape => {
+                            if key == Scancode::Return && item != Item::Back {
+                                continue;
+                            }
+                            if let Err(e) = settings.save(SETTINGS_FILE) {
+                                println!("cannot save {}: {}", SETTINGS_FILE, e);
+                            }
+                            scene = Scene::Title;
+                            cursor = title_items()
+                                .iter()
+                                .position(|item| *item == "SETTINGS")
+                                .unwrap();
+                        }
+                        _ => {}
                     }
                 }
-                _ => {}
+                Scene::Play => {
+                    if key == Scancode::F1 {
+                        remap = Some(Remap::new(&bindings));
+                        remap_paused = paused;
+                        paused = true;
+                        continue;
+                    }
+                    // リプレイの再生中に読み込むと入力と合わなくなるので、保存だけできる
+                    // デイリーチャレンジは最初の1回だけが記録なので、保存してやり直せないようにする
+                    if key == Scancode::F5 && daily.is_some() {
+                        println!("The daily challenge cannot be saved");
+                        continue;
+                    }
+                    if key == Scancode::F5 {
+                        match snapshot::save(&games, options.versus, SNAPSHOT_FILE) {
+                            Ok(()) => println!("Saved {}", SNAPSHOT_FILE),
+                            Err(e) => println!("cannot save {}: {}", SNAPSHOT_FILE, e),
+                        }
+                        continue;
+                    }
+                    if key == Scancode::F8 && options.replay.is_none() {
+                        match load_snapshot(SNAPSHOT_FILE, &options) {
+                            Ok(mut loaded) => {
+                                loaded.unrank();
+                                resume = Some(loaded);
+                                start = true;
+                            }
+                            Err(e) => println!("{}", e),
+                        }
+                        continue;
+                    }
+                    match bindings.action_of(key) {
+                        Some(Action::Quit) => {
+                            // デイリーチャレンジは途中でやめたらその回は終わり
+                            if options.replay.is_none() && daily.is_none() {
+                                suspend(&games, options.versus);
+                            }
+                            sdl2::mixer::Music::halt();
+                            scene = Scene::Title;
+                            cursor = 0;
+                        }
+                        Some(Action::Pause) => paused = !paused,
+                        Some(Action::Restart) => restart = true,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        if scene == Scene::Title && (start || restart) {
+            restart = false;
+            match title_items()[cursor] {
+                "CONTINUE" => match load_snapshot(SUSPEND_FILE, &options) {
+                    Ok(loaded) => {
+                        let _ = fs::remove_file(SUSPEND_FILE);
+                        resume = Some(loaded);
+                        scene = Scene::Play;
+                        start = true;
+                    }
+                    Err(e) => println!("{}", e),
+                },
+                "SETTINGS" => {
+                    scene = Scene::Settings;
+                    cursor = 0;
+                }
+                "QUIT" => break 'running,
+                // 練習とデイリーチャレンジは元のDown!!と同じ遊び方
+                item => {
+                    practice = item == "PRACTICE";
+                    daily_mode = item == "DAILY";
+                    mode = MODES
+                        .iter()
+                        .copied()
+                        .find(|mode| mode.label() == item)
+                        .unwrap_or(Mode::Endless);
+                    scene = Scene::Play;
+                    start = true;
+                }
+            }
+        }
+
+        if scene == Scene::Play && (start || (restart && is_all_over(&games))) {
+            frame = 0;
+            rewind.clear();
+            ghost = None;
+            if daily.take().is_none() {
+                for (ranking, game) in rankings.iter_mut().zip(&games) {
+                    ranking.clone_from(&game.highscore);
+                }
             }
+            if let Some(loaded) = resume.take() {
+                practice = loaded.games.iter().any(|game| !game.ranked);
+                daily_mode = false;
+                mode = loaded.games[0].mode;
+                options.versus = loaded.versus;
+                games = loaded.games;
+                recording = None;
+            } else {
+                if daily_mode && options.replay.is_none() {
+                    daily = Some(Daily::today());
+                }
+                let seed = match (&options.replay, &daily) {
+                    (Some(replay), _) => replay.seed,
+                    (None, Some(today)) => today.seed,
+                    (None, None) => options.seed.unwrap_or_else(time_seed),
+                };
+                let mut header = Replay::new(seed, options.rules, options.players, options.versus);
+                header.mode = mode;
+                header.floors = options.floors.unwrap_or(mode.floors());
+                if mode == Mode::Course {
+                    header.course = options.course.clone();
+                }
+                recording = None;
+                games = (0..options.players).map(|_| header.new_game()).collect();
+                if options.replay.is_none() {
+                    ghost = Ghost::load(&header);
+                    if !practice {
+                        recording = Some(header);
+                    }
+                }
+                for (game, ranking) in games.iter_mut().zip(&rankings) {
+                    game.highscore = match &daily {
+                        Some(today) => {
+                            let mut scores = daily_scores.attempts(&today.date).to_vec();
+                            scores.sort_by(|a, b| b.cmp(a));
+                            scores.truncate(HIGHSCORES as usize);
+                            scores
+                        }
+                        None => ranking.clone(),
+                    };
+                    game.ranked = !practice;
+                }
+                if let Some(today) = &daily {
+                    official = daily_scores.start(&today.date);
+                    if let Err(e) = daily_scores.save(DAILY_FILE) {
+                        println!("cannot save {}: {}", DAILY_FILE, e);
+                    }
+                }
+            }
+            paused = false;
+            music.play(-1)?;
         }
+
         before = now;
         now = timer.ticks();
-        let dt = now - before;
-        game.update(command, dt);
-        render(&mut canvas, &game, &mut resources)?;
 
-        play_sounds(&mut game, &resources);
-        play_music(&mut game);
+        // 再生中は記録された経過時間と操作を使い、最後まで再生したら止める
+        let input = match &options.replay {
+            Some(replay) if !paused => replay.frames.get(frame).map(|f| (f.dt, f.commands.clone())),
+            Some(_) => None,
+            None => Some((if paused { 0 } else { now - before }, commands)),
+        };
+        let rewinding = practice && scene == Scene::Play && !paused && rewind_key;
+        if let (Scene::Play, Some((dt, commands))) = (scene, input) {
+            if rewinding {
+                // 倒れたあとから生きていたところまで戻ったら曲を流し直す
+                if rewind.rewind(&mut games, dt)
+                    && !is_all_over(&games)
+                    && !sdl2::mixer::Music::is_playing()
+                {
+                    music.play(-1)?;
+                }
+                if let Some(ghost) = &mut ghost {
+                    ghost.advance(games[0].now);
+                }
+            } else {
+                let was_over = is_all_over(&games);
+                update_games(&mut games, &commands, dt);
+                frame += 1;
+                if practice {
+                    rewind.record(&games, dt);
+                }
+                if let Some(ghost) = &mut ghost {
+                    ghost.advance(games[0].now);
+                }
+                let finished = !was_over && is_all_over(&games);
+                if let (Some(today), true) = (&daily, finished) {
+                    let score = games.iter().map(|game| game.score).sum();
+                    daily_scores.finish(&today.date, score);
+                    if let Err(e) = daily_scores.save(DAILY_FILE) {
+                        println!("cannot save {}: {}", DAILY_FILE, e);
+                    }
+                    if official {
+                        println!("Daily {}: official score {}", today.date, score);
+                    }
+                }
+                if let Some(recording) = &mut recording {
+                    recording.push(dt, &commands);
+                    if finished {
+                        if let Err(e) = recording.save(REPLAY_FILE) {
+                            println!("cannot save {}: {}", REPLAY_FILE, e);
+                        }
+                        if let Some(record) = mode.record(&games) {
+                            let lower = mode.lower_is_better();
+                            let name = mode.record_name(&games[0]);
+                            if let Some(rank) = records.add(&name, record, lower) {
+                                let label = mode.label();
+                                println!("{}: {} is No.{}", label, time_text(record), rank + 1);
+                            }
+                            if let Err(e) = records.save(RECORDS_FILE) {
+                                println!("cannot save {}: {}", RECORDS_FILE, e);
+                            }
+                        }
+                        // 起動時刻から作った種はもう出ないので、同じ床で遊べるときだけゴーストにする
+                        let repeatable =
+                            options.seed.is_some() || daily.is_some() || recording.course.is_some();
+                        if repeatable {
+                            match ghost::save_if_best(recording, &games) {
+                                Ok(true) => println!("Saved {}", Ghost::path(recording)),
+                                Ok(false) => {}
+                                Err(e) => println!("cannot save the ghost: {}", e),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        resources.texts.sweep();
+        if let Some(state) = &remap {
+            render_remap(&mut canvas, state, &mut resources)?;
+        } else {
+            match scene {
+                Scene::Title => {
+                    let items: Vec<String> =
+                        title_items().iter().map(|item| item.to_string()).collect();
+                    render_menu(&mut canvas, "rust-down", &items, cursor, &mut resources)?;
+                }
+                Scene::Settings => {
+                    let items: Vec<String> = ITEMS
+                        .iter()
+                        .map(|item| format!("{:14}{}", item.label(), item.value(&settings)))
+                        .collect();
+                    render_menu(&mut canvas, "SETTINGS", &items, cursor, &mut resources)?;
+                }
+                Scene::Play => {
+                    let result = if paused {
+                        Some("PAUSE".to_string())
+                    } else if rewinding {
+                        Some("<< REWIND".to_string())
+                    } else {
+                        result_text(&games, options.versus)
+                    };
+                    let mut hud = Hud::default();
+                    if practice {
+                        hud.label = Some("PRACTICE".to_string());
+                    } else if let Some(today) = &daily {
+                        let retry = if official { "" } else { " RETRY" };
+                        hud.label = Some(format!("DAILY {}{}", &today.date[5..], retry));
+                    } else if mode != Mode::Endless {
+                        hud.label = Some(mode.label().to_string());
+                    }
+                    if !mode.ranks_by_score() {
+                        let times = records.get(&mode.record_name(&games[0])).iter();
+                        let lines = times.map(|time| time_text(*time)).collect();
+                        hud.ranking = Some((mode.ranking_title(), lines));
+                    }
+                    render(
+                        &mut canvas,
+                        &games,
+                        ghost.as_ref().map(|ghost| ghost.games.as_slice()),
+                        &hud,
+                        result.as_deref(),
+                        &settings,
+                        &mut resources,
+                    )?;
+
+                    for game in &mut games {
+                        play_sounds(game, &resources);
+                    }
+                    if games.len() == 1 {
+                        play_music(&mut games[0]);
+                    } else {
+                        play_race_music(&mut games);
+                    }
+                }
+            }
+        }
+        // 画像にはデバッグ表示を入れないので、先に保存してから重ねる
+        if scene == Scene::Play && options.screenshot_at == Some(frame) {
+            options.screenshot_at = None;
+            let path = format!("screenshot-frame{}.bmp", frame);
+            save_screenshot(&canvas, &path)?;
+            println!("Saved {}", path);
+        }
+        if screenshot {
+            let path = format!("screenshot-{}.bmp", timestamp());
+            save_screenshot(&canvas, &path)?;
+            println!("Saved {}", path);
+        }
+        if frame_graph.visible {
+            render_frame_graph(&mut canvas, &frame_graph, &settings, &mut resources)?;
+        }
+        canvas.present();
+
+        // VSyncが無いときや、より低いFPSにしたいときは残りの時間を寝て待つ
+        if settings.max_fps > 0 {
+            let target = Duration::from_secs(1) / settings.max_fps;
+            let elapsed = frame_start.elapsed();
+            if elapsed < target {
+                std::thread::sleep(target - elapsed);
+            }
+        }
+    }
+
+    // ウィンドウを閉じたときもプレイ中なら続きを残す
+    if scene == Scene::Play && options.replay.is_none() && daily.is_none() {
+        suspend(&games, options.versus);
     }
 
     Ok(())
 }
 
-fn init_mixer() {
-    let chunk_size = 1_024;
+// ウィンドウを開かずにリプレイを進め、指定のフレーム(無ければ最後)を画像に描いて保存する
+// 画面の無い環境でサムネイルやスクリーンショットを作るのに使う
+// --recordならそこまでをゲーム内時間で一定間隔ごとに描いて連番の画像にする
+fn run_headless(options: &Options, settings: &Settings) -> Result<(), String> {
+    let replay = options.replay.as_ref().ok_or("--headless needs --replay")?;
+    let last = options
+        .screenshot_at
+        .unwrap_or(replay.frames.len())
+        .min(replay.frames.len());
+
+    let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
+    let (screen_w, screen_h) = screen_size(options);
+    let surface = Surface::new(screen_w as u32, screen_h as u32, PixelFormatEnum::RGB24)?;
+    let mut canvas = surface.into_canvas()?;
+    canvas.set_blend_mode(BlendMode::Blend);
+    let texture_creator = canvas.texture_creator();
+    let mut resources = load_resources(&texture_creator, &ttf_context, false);
+
+    let mut games: Vec<Game> = (0..replay.games).map(|_| replay.new_game()).collect();
+    let mut draw = |games: &[Game], path: &str| {
+        let result = result_text(games, options.versus);
+        render(
+            &mut canvas,
+            games,
+            None,
+            &Hud::default(),
+            result.as_deref(),
+            settings,
+            &mut resources,
+        )?;
+        canvas.surface().save_bmp(path)
+    };
+
+    if options.record {
+        let dir = format!("recording-{}", timestamp());
+        let mut recorder = Recorder::start(&dir).map_err(|e| e.to_string())?;
+        // 最初の1枚は始まったところ
+        if let Some(path) = recorder.advance(0) {
+            draw(&games, &path)?;
+        }
+        for frame in &replay.frames[..last] {
+            update_games(&mut games, &frame.commands, frame.dt);
+            if let Some(path) = recorder.advance(frame.dt) {
+                draw(&games, &path)?;
+            }
+        }
+        return recorder.finish().map_err(|e| e.to_string());
+    }
+
+    for frame in &replay.frames[..last] {
+        update_games(&mut games, &frame.commands, frame.dt);
+    }
+    let path = format!("screenshot-frame{}.bmp", last);
+    draw(&games, &path)?;
+    println!("Saved {}", path);
+    Ok(())
+}
+
+// ファイル名に使う現在時刻(ミリ秒まで)
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}{:03}", now.as_secs(), now.subsec_millis())
+}
+
+// 直近のフレーム時間。F3でグラフを表示してフレームの間隔が揃っているか確かめる
+struct FrameGraph {
+    times: VecDeque<Duration>,
+    visible: bool,
+}
+
+impl FrameGraph {
+    fn new() -> FrameGraph {
+        FrameGraph {
+            times: VecDeque::with_capacity(FRAME_GRAPH_LEN),
+            visible: false,
+        }
+    }
+
+    fn push(&mut self, time: Duration) {
+        if self.times.len() >= FRAME_GRAPH_LEN {
+            self.times.pop_front();
+        }
+        self.times.push_back(time);
+    }
+}
+
+// 描いたばかりの画面を読み出して保存する。presentの前に呼ぶ
+fn save_screenshot(canvas: &Canvas<Window>, path: &str) -> Result<(), String> {
+    // read_pixelsは出力全体の幅で1行を詰めるので、拡大後のビューポートの大きさで切り出す
+    let (output_w, _) = canvas.output_size()?;
+    let (scale_x, scale_y) = canvas.scale();
+    let viewport = canvas.viewport();
+    let w = (viewport.width() as f32 * scale_x) as u32;
+    let h = (viewport.height() as f32 * scale_y) as u32;
+    let format = PixelFormatEnum::RGB24;
+    let mut pixels = canvas.read_pixels(None, format)?;
+    let surface = Surface::from_data(&mut pixels, w, h, output_w * 3, format)?;
+    surface.save_bmp(path)?;
+    Ok(())
+}
+
+fn apply_volume(settings: &Settings) {
+    sdl2::mixer::Music::set_volume(settings.music_volume_sdl());
+    sdl2::mixer::Channel::all().set_volume(settings.sound_volume_sdl());
+}
+
+// ウィンドウの大きさとフルスクリーンを設定に合わせる
+// 拡大率はウィンドウモードでの初期の大きさで、ウィンドウを広げればさらに大きく描く
+fn apply_window(settings: &Settings, canvas: &mut Canvas<Window>) -> Result<(), String> {
+    let (w, h) = canvas.logical_size();
+    let window = canvas.window_mut();
+    if settings.fullscreen {
+        window.set_fullscreen(FullscreenType::Desktop)?;
+    } else {
+        window.set_fullscreen(FullscreenType::Off)?;
+        window
+            .set_size(w * settings.scale, h * settings.scale)
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// 論理的な画面の大きさ。ゲームを横に並べる
+fn screen_size(options: &Options) -> (i32, i32) {
+    let layout = Layout::new(options.rules.wid, options.rules.hei, 0);
+    (layout.pane_w * options.players as i32, layout.screen_h)
+}
+
+// 2人プレイでは同じ種を使い、同じ床が出るようにする
+fn new_games(options: &Options, seed: u64) -> Vec<Game> {
+    (0..options.players)
+        .map(|_| Game::new(options.rules, seed))
+        .collect()
+}
+
+// 中断したゲームがあるときはタイトルで続きから始められる
+fn title_items() -> Vec<&'static str> {
+    let mut items = vec![
+        Mode::Endless.label(),
+        "DAILY",
+        Mode::TimeAttack.label(),
+        Mode::Survival.label(),
+        "PRACTICE",
+        "SETTINGS",
+        "QUIT",
+    ];
+    if std::path::Path::new(SUSPEND_FILE).exists() {
+        items.insert(0, "CONTINUE");
+    }
+    items
+}
+
+// 終わっていないゲームを中断して、次にタイトルから続けられるようにする
+fn suspend(games: &[Game], versus: bool) {
+    if is_all_over(games) {
+        return;
+    }
+    if let Err(e) = snapshot::save(games, versus, SUSPEND_FILE) {
+        println!("cannot save {}: {}", SUSPEND_FILE, e);
+    }
+}
+
+// ウィンドウの大きさは起動時に決まるので、フィールドの大きさと数が同じものだけ読み込める
+fn load_snapshot(path: &str, options: &Options) -> Result<Snapshot, String> {
+    let snapshot = snapshot::load(path)?;
+    let fits = snapshot.games.len() == options.players
+        && snapshot.games.iter().all(|game| {
+            game.rules.wid == options.rules.wid
+                && game.rules.hei == options.rules.hei
+                && game.rules.players == options.rules.players
+        });
+    if !fits {
+        return Err(format!("{}: the field size does not match", path));
+    }
+    Ok(snapshot)
+}
+
+// クリアしたゲームは倒れた人が消えるのを待たずに終わる
+fn is_all_over(games: &[Game]) -> bool {
+    games.iter().all(|game| {
+        game.is_over && (game.cleartime.is_some() || game.hitos.iter().all(|hito| hito.hide))
+    })
+}
+
+// 2人プレイの勝敗。レースはモードで決まる値の大きい方、対戦は後まで生き残った方が勝ち
+// 1人ならモードによってクリアしたタイムなどを出す
+fn result_text(games: &[Game], versus: bool) -> Option<String> {
+    if !is_all_over(games) {
+        return None;
+    }
+    let mode = games[0].mode;
+    let records: Vec<u32> = if games.len() > 1 {
+        games.iter().map(|game| mode.race_value(game)).collect()
+    } else if versus {
+        // クリアしたときに生き残っていた人は最後まで残ったことにする
+        games[0]
+            .hitos
+            .iter()
+            .map(|hito| if hito.dead { hito.deadtime } else { u32::MAX })
+            .collect()
+    } else {
+        return mode.result_text(games);
+    };
+    let best = *records.iter().max().unwrap();
+    let winners: Vec<usize> = (0..records.len()).filter(|i| records[*i] == best).collect();
+    if winners.len() == 1 {
+        Some(format!("{}P WIN!", winners[0] + 1))
+    } else {
+        Some("DRAW".to_string())
+    }
+}
+
+fn init_mixer(settings: &Settings) {
     mixer::open_audio(
-        22010, // デフォルトは22050
+        settings.frequency,
         mixer::AUDIO_S8,
         1, // monoral
-        chunk_size,
+        settings.chunk_size,
     )
     .expect("cannot open audio");
     mixer::allocate_channels(sound::MAX_CHANNELS);
 }
 
-fn load_resources<'a>(
-    texture_creator: &'a TextureCreator<WindowContext>,
-    #[allow(unused_variables)] canvas: &mut Canvas<Window>,
+// 音を鳴らさないときはミキサーを開かないので、効果音は読み込まない
+fn load_resources<'a, C>(
+    texture_creator: &'a TextureCreator<C>,
     ttf_context: &'a Sdl2TtfContext,
-) -> Resources<'a> {
+    with_sounds: bool,
+) -> Resources<'a, C> {
     let mut resources = Resources {
         images: HashMap::new(),
         chunks: HashMap::new(),
         fonts: HashMap::new(),
+        texts: TextCache::new(texture_creator),
     };
 
     let entries = fs::read_dir("resources/image").unwrap();
@@ -167,7 +1140,7 @@ fn load_resources<'a>(
     }
 
     let entries = fs::read_dir("./resources/sound").unwrap();
-    for entry in entries {
+    for entry in entries.filter(|_| with_sounds) {
         let path = entry.unwrap().path();
         let path_str = path.to_str().unwrap();
         if path_str.ends_with(".wav") {
@@ -189,8 +1162,8 @@ fn load_resources<'a>(
     resources
 }
 
-fn load_font<'a>(
-    resources: &mut Resources<'a>,
+fn load_font<'a, C>(
+    resources: &mut Resources<'a, C>,
     ttf_context: &'a Sdl2TtfContext,
     path_str: &str,
     point_size: u16,
@@ -202,154 +1175,481 @@ fn load_font<'a>(
     resources.fonts.insert(key.to_string(), font);
 }
 
-fn render(
-    canvas: &mut Canvas<Window>,
-    game: &Game,
-    resources: &mut Resources,
+fn render<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    games: &[Game],
+    ghost: Option<&[Game]>,
+    hud: &Hud,
+    result: Option<&str>,
+    settings: &Settings,
+    resources: &mut Resources<T::Context>,
 ) -> Result<(), String> {
     canvas.set_draw_color(Color::RGB(0, 0, 0));
     canvas.clear();
 
-    let font = resources.fonts.get_mut("boxfont").unwrap();
+    for (i, game) in games.iter().enumerate() {
+        let layout = Layout::new(game.wid, game.hei, i as i32);
+        let ghost = ghost.and_then(|ghost| ghost.get(i));
+        render_game(canvas, game, ghost, hud, &layout, settings, resources)?;
+    }
+
+    // render winner or pause
+    if let Some(text) = result {
+        let layout = Layout::new(games[0].wid, games[0].hei, 0);
+        let center_x = layout.pane_w * games.len() as i32 / 2;
+        let center_y = layout.screen_h / 2;
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 192));
+        canvas.fill_rect(Rect::new(center_x - 120, center_y - 30, 240, 60))?;
+        render_font(
+            canvas,
+            resources,
+            text.to_string(),
+            center_x,
+            center_y - 24,
+            Color::RGB(255, 255, 0),
+            true,
+        );
+        if is_all_over(games) {
+            render_font(
+                canvas,
+                resources,
+                "PUSH SPACE".to_string(),
+                center_x,
+                center_y,
+                Color::RGB(255, 255, 255),
+                true,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// 1フレームを縦2ピクセル/msの棒で描く。目標のフレーム時間を超えたら赤
+fn render_frame_graph<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    graph: &FrameGraph,
+    settings: &Settings,
+    resources: &mut Resources<T::Context>,
+) -> Result<(), String> {
+    let (_, screen_h) = canvas.logical_size();
+    let h = 80;
+    let x0 = 8;
+    let y0 = screen_h as i32 - h - 8;
+    let target_fps = if settings.max_fps > 0 {
+        settings.max_fps
+    } else {
+        60
+    };
+    let target = Duration::from_secs(1) / target_fps;
+
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 192));
+    canvas.fill_rect(Rect::new(x0, y0, FRAME_GRAPH_LEN as u32 * 2, h as u32))?;
+    for (i, time) in graph.times.iter().enumerate() {
+        let bar_h = ((time.as_secs_f32() * 1000.0 * 2.0) as i32).clamp(1, h);
+        canvas.set_draw_color(if *time > target {
+            Color::RGB(255, 64, 64)
+        } else {
+            Color::RGB(64, 255, 64)
+        });
+        canvas.fill_rect(Rect::new(
+            x0 + i as i32 * 2,
+            y0 + h - bar_h,
+            2,
+            bar_h as u32,
+        ))?;
+    }
+    let target_y = y0 + h - (target.as_secs_f32() * 1000.0 * 2.0) as i32;
+    canvas.set_draw_color(Color::RGB(255, 255, 0));
+    canvas.draw_line(
+        (x0, target_y),
+        (x0 + FRAME_GRAPH_LEN as i32 * 2 - 1, target_y),
+    )?;
+
+    let max = graph.times.iter().max().copied().unwrap_or_default();
+    let avg = graph.times.iter().sum::<Duration>() / graph.times.len().max(1) as u32;
+    render_font(
+        canvas,
+        resources,
+        format!(
+            "AVG:{:.1}ms MAX:{:.1}ms",
+            avg.as_secs_f32() * 1000.0,
+            max.as_secs_f32() * 1000.0
+        ),
+        x0,
+        y0 - 20,
+        Color::RGB(255, 255, 255),
+        false,
+    );
+
+    Ok(())
+}
+
+fn render_menu<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    title: &str,
+    items: &[String],
+    cursor: usize,
+    resources: &mut Resources<T::Context>,
+) -> Result<(), String> {
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
 
     render_font(
         canvas,
-        font,
-        format!("FPS:{}", game.system.fps).to_string(),
-        640 - 80,
-        0,
+        resources,
+        title.to_string(),
+        CHAR * 2,
+        CHAR,
+        Color::RGB(255, 255, 255),
+        false,
+    );
+
+    for (i, item) in items.iter().enumerate() {
+        let (text, color) = if i == cursor {
+            (format!("> {}", item), Color::RGB(255, 255, 0))
+        } else {
+            (format!("  {}", item), Color::RGB(200, 255, 255))
+        };
+        render_font(
+            canvas,
+            resources,
+            text,
+            CHAR * 2,
+            CHAR * 4 + 25 * i as i32,
+            color,
+            false,
+        );
+    }
+
+    render_font(
+        canvas,
+        resources,
+        "UP/DOWN: SELECT  LEFT/RIGHT: CHANGE  ENTER: OK".to_string(),
+        CHAR * 2,
+        SCREEN_H - CHAR * 3,
         Color::RGB(127, 127, 127),
         false,
     );
 
+    Ok(())
+}
+
+fn render_remap<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    remap: &Remap,
+    resources: &mut Resources<T::Context>,
+) -> Result<(), String> {
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+
     render_font(
         canvas,
-        font,
-        "SCORE RANKING".to_string(),
-        field::RIGHT + 32,
+        resources,
+        "KEY CONFIG".to_string(),
+        CHAR * 2,
+        CHAR,
+        Color::RGB(255, 255, 255),
+        false,
+    );
+
+    for (i, action) in ACTIONS.iter().enumerate() {
+        let y = CHAR * 4 + 25 * i as i32;
+        let (keys, color) = if i == remap.index {
+            let keys = remap
+                .pressed
+                .iter()
+                .map(|key| key.name())
+                .collect::<Vec<&str>>()
+                .join(", ");
+            (format!("{}_", keys), Color::RGB(255, 255, 0))
+        } else {
+            let keys = remap.bindings.key_names(*action);
+            let keys = if keys.is_empty() {
+                "-".to_string()
+            } else {
+                keys
+            };
+            (keys, Color::RGB(200, 255, 255))
+        };
+        render_font(
+            canvas,
+            resources,
+            action.label().to_string(),
+            CHAR * 2,
+            y,
+            color,
+            false,
+        );
+        render_font(canvas, resources, keys, CHAR * 14, y, color, false);
+    }
+
+    render_font(
+        canvas,
+        resources,
+        "PRESS KEYS, ENTER: NEXT  ESC: CANCEL".to_string(),
+        CHAR * 2,
+        SCREEN_H - CHAR * 3,
+        Color::RGB(127, 127, 127),
+        false,
+    );
+
+    Ok(())
+}
+
+fn render_game<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    game: &Game,
+    ghost: Option<&Game>,
+    hud: &Hud,
+    layout: &Layout,
+    settings: &Settings,
+    resources: &mut Resources<T::Context>,
+) -> Result<(), String> {
+    if settings.show_fps {
+        render_font(
+            canvas,
+            resources,
+            format!("FPS:{}", game.system.fps).to_string(),
+            layout.pane_x + layout.pane_w - 80,
+            0,
+            Color::RGB(127, 127, 127),
+            false,
+        );
+    }
+
+    let (title, ranking) = match &hud.ranking {
+        Some((title, lines)) => (title.to_string(), lines.clone()),
+        None => (
+            "SCORE RANKING".to_string(),
+            game.highscore
+                .iter()
+                .map(|score| score.to_string())
+                .collect(),
+        ),
+    };
+    render_font(
+        canvas,
+        resources,
+        title,
+        layout.hud_x,
         2,
         Color::RGB(255, 255, 255),
         false,
     );
 
+    // モードによってはスコアの代わりにゴールまでの深さを出し、経過時間も出す
+    let (score, time) = game.mode.hud(game);
     render_font(
         canvas,
-        font,
-        format!("SCORE:{}", game.score).to_string(),
-        field::RIGHT + 32,
-        300,
+        resources,
+        score,
+        layout.hud_x,
+        layout.hud_y(300),
         Color::RGB(255, 255, 255),
         false,
     );
+    if let Some(time) = time {
+        render_font(
+            canvas,
+            resources,
+            time,
+            layout.hud_x,
+            layout.hud_y(400),
+            Color::RGB(255, 255, 255),
+            false,
+        );
+    }
 
     render_font(
         canvas,
-        font,
+        resources,
         "LIFE".to_string(),
-        field::RIGHT + 32,
-        330,
+        layout.hud_x,
+        layout.hud_y(330),
         Color::RGB(255, 255, 255),
         false,
     );
 
     render_font(
         canvas,
-        font,
+        resources,
         "Ver.1.0.0".to_string(),
-        640 - 106,
-        460,
+        layout.pane_x + layout.pane_w - 106,
+        layout.screen_h - 20,
         Color::RGB(127, 127, 127),
         false,
     );
 
     // render high scores
-    for (i, score) in game.highscore.iter().enumerate() {
+    for (i, line) in ranking.iter().enumerate() {
         render_font(
             canvas,
-            font,
-            format!("{:2}: {:6}", i + 1, score).to_string(),
-            field::RIGHT + 32,
+            resources,
+            format!("{:2}: {:>6}", i + 1, line),
+            layout.hud_x,
             25 * (i + 1) as i32,
             Color::RGB(200, 255, 255),
             false,
         );
     }
+    if let Some(label) = &hud.label {
+        render_font(
+            canvas,
+            resources,
+            label.to_string(),
+            layout.hud_x,
+            layout.hud_y(275),
+            Color::RGB(255, 255, 0),
+            false,
+        );
+    }
 
     // render walls
-    for i in 0..field::HEI {
+    for i in 0..layout.hei {
         let image = resources.images.get("wall.bmp").unwrap();
         canvas
             .copy(
                 &image.texture,
                 Rect::new(0, 0, image.w, image.h),
-                Rect::new(field::LEFT - CHAR, CHAR * i, image.w, image.h),
+                Rect::new(layout.left - CHAR, layout.top + CHAR * i, image.w, image.h),
             )
             .unwrap();
         canvas
             .copy(
                 &image.texture,
                 Rect::new(0, 0, image.w, image.h),
-                Rect::new(field::RIGHT + 1, CHAR * i, image.w, image.h),
+                Rect::new(layout.right + 1, layout.top + CHAR * i, image.w, image.h),
             )
             .unwrap();
     }
 
+    // render ceiling
+    if game.rules.ceiling {
+        let image = resources.images.get("floor.bmp").unwrap();
+        for x in 0..layout.wid {
+            canvas.copy_ex(
+                &image.texture,
+                Rect::new(CHAR, 0, CHAR as u32, CHAR as u32),
+                Rect::new(layout.left + x * CHAR, layout.top, CHAR as u32, CHAR as u32),
+                0.0,
+                None,
+                false,
+                true,
+            )?;
+        }
+    }
+
     // render floors and items
-    for y in 0..field::HEI {
-        for x in 0..field::WID {
+    for y in 0..layout.hei {
+        for x in 0..layout.wid {
             match game.data[y as usize][x as usize] {
                 Chara::BLOCK => {
-                    render_chara(canvas, resources, x, y, "floor.bmp", 0);
+                    render_chara(canvas, resources, layout, x, y, "floor.bmp", 0);
                 }
                 Chara::HARI => {
-                    render_chara(canvas, resources, x, y, "floor.bmp", 1);
+                    render_chara(canvas, resources, layout, x, y, "floor.bmp", 1);
                 }
                 Chara::STAR => {
-                    render_chara(canvas, resources, x, y, "item.bmp", 0);
+                    render_chara(canvas, resources, layout, x, y, "item.bmp", 0);
                 }
                 Chara::PARA => {
-                    render_chara(canvas, resources, x, y, "item.bmp", 1);
+                    render_chara(canvas, resources, layout, x, y, "item.bmp", 1);
                 }
                 Chara::OMORI => {
-                    render_chara(canvas, resources, x, y, "item.bmp", 2);
+                    render_chara(canvas, resources, layout, x, y, "item.bmp", 2);
                 }
                 _ => {}
             }
         }
     }
 
+    // render enemies
+    for enemy in &game.enemies {
+        let image = resources.images.get("enemy.bmp").unwrap();
+        let frame = if enemy.dir > 0 { 0 } else { 2 } + enemy.anime;
+        canvas
+            .copy(
+                &image.texture,
+                Rect::new(CHAR * frame, 0, CHAR as u32, CHAR as u32),
+                Rect::new(
+                    layout.left + enemy.x * CHAR,
+                    layout.top + enemy.y * CHAR,
+                    CHAR as u32,
+                    CHAR as u32,
+                ),
+            )
+            .unwrap();
+    }
+
+    // render ghost
+    // 前の回の自分を半透明で描く。フィールドは今のゲームのものを使う
+    // 画像の背景は黒なので、加算で重ねると人だけが薄く浮かぶ
+    for hito in ghost.iter().flat_map(|ghost| &ghost.hitos) {
+        if hito.hide {
+            continue;
+        }
+        let image = resources.images.get_mut("hito.bmp").unwrap();
+        image.texture.set_blend_mode(BlendMode::Add);
+        image.texture.set_alpha_mod(128);
+        canvas
+            .copy(
+                &image.texture,
+                Rect::new(0, 0, CHAR as u32, CHAR as u32),
+                Rect::new(
+                    layout.left + hito.x * CHAR,
+                    layout.top + hito.y * CHAR,
+                    CHAR as u32,
+                    CHAR as u32,
+                ),
+            )
+            .unwrap();
+        image.texture.set_alpha_mod(255);
+        image.texture.set_blend_mode(BlendMode::None);
+    }
+
     // render hito
-    if !game.hito.hide {
-        let image = if game.hito.omori {
-            resources.images.get("omori.bmp").unwrap()
-        } else if game.hito.para {
-            resources.images.get("para.bmp").unwrap()
+    for (i, hito) in game.hitos.iter().enumerate() {
+        if hito.hide {
+            continue;
+        }
+        let image = if hito.omori {
+            resources.images.get_mut("omori.bmp").unwrap()
+        } else if hito.para {
+            resources.images.get_mut("para.bmp").unwrap()
         } else {
-            resources.images.get("hito.bmp").unwrap()
+            resources.images.get_mut("hito.bmp").unwrap()
         };
+        // 2人目以降は色を変えて見分ける
+        let (r, g, b) = PLAYER_COLORS[i % PLAYER_COLORS.len()];
+        image.texture.set_color_mod(r, g, b);
         canvas
             .copy(
                 &image.texture,
-                Rect::new(CHAR * game.hito.hitonum, 0, CHAR as u32, CHAR as u32),
+                Rect::new(CHAR * hito.hitonum, 0, CHAR as u32, CHAR as u32),
                 Rect::new(
-                    field::LEFT + game.hito.x * CHAR,
-                    field::TOP + game.hito.y * CHAR,
+                    layout.left + hito.x * CHAR,
+                    layout.top + hito.y * CHAR,
                     CHAR as u32,
                     CHAR as u32,
                 ),
             )
             .unwrap();
+        image.texture.set_color_mod(255, 255, 255);
     }
 
     // render sakebi
-    if game.is_over && !game.hito.hide {
+    for hito in &game.hitos {
+        if !hito.dead || hito.hide {
+            continue;
+        }
         let image = resources.images.get("sakebi.bmp").unwrap();
-        let x = if game.hito.x < field::WID / 2 {
-            field::LEFT + (game.hito.x + 1) * CHAR
+        let x = if hito.x < layout.wid / 2 {
+            layout.left + (hito.x + 1) * CHAR
         } else {
-            field::LEFT + (game.hito.x - 2) * CHAR
+            layout.left + (hito.x - 2) * CHAR
         };
-        let y = field::TOP + game.hito.y * CHAR;
+        let y = layout.top + hito.y * CHAR;
         canvas
             .copy(
                 &image.texture,
@@ -364,8 +1664,8 @@ fn render(
         match effect._type {
             EffectType::BREAK => {
                 let image = resources.images.get("effect.bmp").unwrap();
-                let dx = field::LEFT + effect.x * CHAR - 16;
-                let dy = field::TOP + effect.y * CHAR - 24;
+                let dx = layout.left + effect.x * CHAR - 16;
+                let dy = layout.top + effect.y * CHAR - 24;
                 canvas
                     .copy(
                         &image.texture,
@@ -376,8 +1676,8 @@ fn render(
             }
             EffectType::PANG => {
                 let image = resources.images.get("effect.bmp").unwrap();
-                let dx = field::LEFT + effect.x * CHAR - 16;
-                let dy = field::TOP + effect.y * CHAR - 24;
+                let dx = layout.left + effect.x * CHAR - 16;
+                let dy = layout.top + effect.y * CHAR - 24;
                 canvas
                     .copy(
                         &image.texture,
@@ -385,34 +1685,59 @@ fn render(
                         Rect::new(dx, dy, 48, 24),
                     )
                     .unwrap();
+            }
+            EffectType::CRUSH => {
+                let image = resources.images.get("enemy.bmp").unwrap();
+                let dx = layout.left + effect.x * CHAR;
+                let dy = layout.top + effect.y * CHAR;
+                canvas
+                    .copy(
+                        &image.texture,
+                        Rect::new(CHAR * effect.state, CHAR, CHAR as u32, CHAR as u32),
+                        Rect::new(dx, dy, CHAR as u32, CHAR as u32),
+                    )
+                    .unwrap();
             } // EffectType::PTS => todo!(),
         }
     }
 
     // render gauge
-    if game.life > 0 {
-        let color = if game.gauge.is_red {
-            Color::RGB(255, 0, 0)
-        } else {
-            Color::RGB(255, 255, 255)
-        };
-        canvas.set_draw_color(color);
-        canvas.fill_rect(Rect::new(
-            field::RIGHT + 80,
-            SCREEN_H / 10 * 7,
-            (((SCREEN_W - (CHAR * field::WID) - 108) * game.life) / 100) as u32,
-            16,
-        ))?;
+    for (i, hito) in game.hitos.iter().enumerate() {
+        let y = layout.hud_y(SCREEN_H / 10 * 7) + 24 * i as i32;
+        if game.hitos.len() > 1 {
+            render_font(
+                canvas,
+                resources,
+                format!("{}P", i + 1),
+                layout.hud_x + 16,
+                y - 4,
+                Color::RGB(255, 255, 255),
+                false,
+            );
+        }
+        if hito.life > 0 {
+            let color = if hito.gauge.is_red {
+                Color::RGB(255, 0, 0)
+            } else {
+                Color::RGB(255, 255, 255)
+            };
+            canvas.set_draw_color(color);
+            canvas.fill_rect(Rect::new(
+                layout.hud_x + 48,
+                y,
+                (((HUD_W - 76) * hito.life) / 100) as u32,
+                16,
+            ))?;
+        }
     }
 
-    canvas.present();
-
     Ok(())
 }
 
-fn render_chara(
-    canvas: &mut Canvas<Window>,
-    resources: &mut Resources,
+fn render_chara<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    resources: &mut Resources<T::Context>,
+    layout: &Layout,
     x: i32,
     y: i32,
     image: &str,
@@ -423,26 +1748,27 @@ fn render_chara(
         .copy(
             &image.texture,
             Rect::new(CHAR * image_index as i32, 0, CHAR as u32, CHAR as u32),
-            Rect::new(CHAR * (x + 1), CHAR * y, CHAR as u32, CHAR as u32),
+            Rect::new(
+                layout.left + CHAR * x,
+                layout.top + CHAR * y,
+                CHAR as u32,
+                CHAR as u32,
+            ),
         )
         .unwrap();
 }
 
-fn render_font(
-    canvas: &mut Canvas<Window>,
-    font: &sdl2::ttf::Font,
+fn render_font<T: RenderTarget>(
+    canvas: &mut Canvas<T>,
+    resources: &mut Resources<T::Context>,
     text: String,
     x: i32,
     y: i32,
     color: Color,
     center: bool,
 ) {
-    let texture_creator = canvas.texture_creator();
-
-    let surface = font.render(&text).blended(color).unwrap();
-    let texture = texture_creator
-        .create_texture_from_surface(&surface)
-        .unwrap();
+    let font = &resources.fonts["boxfont"];
+    let texture = resources.texts.get(font, text, color);
     let x: i32 = if center {
         x - texture.query().width as i32 / 2
     } else {
@@ -450,14 +1776,14 @@ fn render_font(
     };
     canvas
         .copy(
-            &texture,
+            texture,
             None,
             Rect::new(x, y, texture.query().width, texture.query().height),
         )
         .unwrap();
 }
 
-fn play_sounds(game: &mut Game, resources: &Resources) {
+fn play_sounds(game: &mut Game, resources: &Resources<WindowContext>) {
     for sound_key in &game.requested_sounds {
         let chunk = resources
             .chunks
@@ -494,3 +1820,16 @@ fn play_music(game: &mut Game) {
     }
     game.requested_musics = Vec::new();
 }
+
+// 2人プレイでは片方の無敵やゲームオーバーで曲を止めず、全員が倒れたときだけ止める
+fn play_race_music(games: &mut [Game]) {
+    let is_halted = games
+        .iter()
+        .any(|game| game.requested_musics.contains(&"halt"));
+    if is_halted && games.iter().all(|game| game.is_over) {
+        sdl2::mixer::Music::halt();
+    }
+    for game in games {
+        game.requested_musics = Vec::new();
+    }
+}
@@ -0,0 +1,215 @@
+use crate::floor::Floors;
+use crate::model::{Game, ITEM_PERCENT};
+
+pub const TIME_ATTACK_GOAL: i32 = 500; // タイムアタックでクリアになるスコア(スクロール数)
+pub const SURVIVAL_RAMP: u32 = 120_000; // サバイバルで落ちる速さが1倍分上がるまでの時間(ms)
+pub const SURVIVAL_ITEM_PERCENT: i32 = 5;
+
+// 遊び方。Rulesはフィールドの形や敵の有無を決め、Modeは床の作り方・落ちる速さ・アイテムの出方・
+// 終わり方と、何で順位を付けてスコア欄に何を出すかを決める
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Mode {
+    Endless,    // 元のDown!!と同じ。倒れるまでに潜った深さ(スコア)を競う
+    TimeAttack, // 決まった深さに着くまでの速さを競う
+    Survival,   // 落ちる速さがだんだん上がり、アイテムも少ない。生き延びた時間を競う
+    Course,     // 手で書いたコース(Game::course)を最後まで進む速さを競う
+}
+
+pub const MODES: [Mode; 4] = [
+    Mode::Endless,
+    Mode::TimeAttack,
+    Mode::Survival,
+    Mode::Course,
+];
+
+impl Mode {
+    // ファイルに書く名前
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Endless => "endless",
+            Mode::TimeAttack => "timeattack",
+            Mode::Survival => "survival",
+            Mode::Course => "course",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Mode> {
+        MODES.iter().copied().find(|mode| mode.name() == name)
+    }
+
+    // タイトル画面とスコア欄に出す名前
+    pub fn label(self) -> &'static str {
+        match self {
+            Mode::Endless => "START",
+            Mode::TimeAttack => "TIME ATTACK",
+            Mode::Survival => "SURVIVAL",
+            Mode::Course => "COURSE",
+        }
+    }
+
+    // このスコアに着いたらクリア。コースのゴールはコースの長さで決まる(Game::goal)
+    pub fn goal(self) -> Option<i32> {
+        match self {
+            Mode::TimeAttack => Some(TIME_ATTACK_GOAL),
+            Mode::Endless | Mode::Survival | Mode::Course => None,
+        }
+    }
+
+    // 床の作り方。サバイバルは速くなっても必ず逃げ道があるようにする
+    pub fn floors(self) -> Floors {
+        match self {
+            Mode::Survival => Floors::SafePath,
+            Mode::Endless | Mode::TimeAttack | Mode::Course => Floors::Classic,
+        }
+    }
+
+    // アイテムが出る確率(%)
+    pub fn item_percent(self) -> i32 {
+        match self {
+            Mode::Survival => SURVIVAL_ITEM_PERCENT,
+            Mode::Endless | Mode::TimeAttack | Mode::Course => ITEM_PERCENT,
+        }
+    }
+
+    // アイテムで決まったスクロールの間隔を、経過時間nowに合わせて変える
+    pub fn fall_wait(self, wait: i32, now: u32) -> i32 {
+        match self {
+            Mode::Survival => {
+                let ramp = SURVIVAL_RAMP as u64;
+                ((wait as u64 * ramp / (ramp + now as u64)) as i32).max(1)
+            }
+            Mode::Endless | Mode::TimeAttack | Mode::Course => wait,
+        }
+    }
+
+    // 経過時間で落ちる速さが変わるか
+    pub fn ramps(self) -> bool {
+        self == Mode::Survival
+    }
+
+    // Game::highscoreのスコアのランキングで順位を付けるか。falseならrecordを使う
+    pub fn ranks_by_score(self) -> bool {
+        self == Mode::Endless
+    }
+
+    // 終わったゲームの記録(ms)。タイムアタックやコースでクリアできなかったときはNone
+    pub fn record(self, games: &[Game]) -> Option<u32> {
+        match self {
+            Mode::Endless => None,
+            Mode::TimeAttack | Mode::Course => games.iter().filter_map(|game| game.cleartime).min(),
+            Mode::Survival => games
+                .iter()
+                .flat_map(|game| &game.hitos)
+                .map(|hito| hito.deadtime)
+                .max(),
+        }
+    }
+
+    // recordは小さいほど良いか
+    pub fn lower_is_better(self) -> bool {
+        matches!(self, Mode::TimeAttack | Mode::Course)
+    }
+
+    // records.cfgでの名前。コースはコースごとに、床の作り方を変えたときはその作り方ごとに分ける
+    pub fn record_name(self, game: &Game) -> String {
+        match (self, &game.course) {
+            (Mode::Course, Some(course)) => format!("course.{}", course.id),
+            _ if game.floors != self.floors() => {
+                format!("{}.{}", self.name(), game.floors.name())
+            }
+            _ => self.name().to_string(),
+        }
+    }
+
+    pub fn ranking_title(self) -> &'static str {
+        match self {
+            Mode::Endless => "SCORE RANKING",
+            Mode::TimeAttack | Mode::Course => "TIME RANKING",
+            Mode::Survival => "LONGEST TIMES",
+        }
+    }
+
+    // 1人で遊んで終わったときに出す文字
+    pub fn result_text(self, games: &[Game]) -> Option<String> {
+        let record = time_text(self.record(games)?);
+        match self {
+            Mode::Endless => None,
+            Mode::TimeAttack | Mode::Course => Some(format!("CLEAR {}", record)),
+            Mode::Survival => Some(format!("SURVIVED {}", record)),
+        }
+    }
+
+    // レースで勝ち負けを決める値。大きい方が勝ち
+    pub fn race_value(self, game: &Game) -> u32 {
+        match self {
+            Mode::Endless => game.score as u32,
+            // クリアした方はスコアがゴールに届いていない方より必ず大きくなる
+            Mode::TimeAttack | Mode::Course => match game.cleartime {
+                Some(time) => u32::MAX - time,
+                None => game.score as u32,
+            },
+            Mode::Survival => game.hitos.iter().map(|hito| hito.deadtime).max().unwrap(),
+        }
+    }
+
+    // スコア欄のスコアの行と、その下に出す行
+    pub fn hud(self, game: &Game) -> (String, Option<String>) {
+        match self {
+            Mode::Endless => (format!("SCORE:{}", game.score), None),
+            Mode::TimeAttack | Mode::Course => (
+                format!("DEPTH:{}/{}", game.score, game.goal().unwrap_or(0)),
+                Some(format!(
+                    "TIME:{}",
+                    time_text(game.cleartime.unwrap_or(game.now))
+                )),
+            ),
+            Mode::Survival => {
+                // 全員倒れたら最後に倒れたときで止める
+                let time = if game.hitos.iter().all(|hito| hito.dead) {
+                    self.race_value(game)
+                } else {
+                    game.now
+                };
+                (
+                    format!("SCORE:{}", game.score),
+                    Some(format!("TIME:{}", time_text(time))),
+                )
+            }
+        }
+    }
+}
+
+// ミリ秒を 1:23.45 の形にする
+pub fn time_text(ms: u32) -> String {
+    format!("{}:{:02}.{:02}", ms / 60000, ms / 1000 % 60, ms % 1000 / 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::wait;
+
+    #[test]
+    fn survival_falls_faster_over_time() {
+        let mode = Mode::Survival;
+        assert_eq!(mode.fall_wait(wait::FALL, 0), wait::FALL);
+        assert_eq!(mode.fall_wait(wait::FALL, SURVIVAL_RAMP), wait::FALL / 2);
+        let waits: Vec<i32> = (0..20)
+            .map(|minute| mode.fall_wait(wait::FALL, minute * 60_000))
+            .collect();
+        assert!(waits.windows(2).all(|w| w[0] >= w[1]));
+        assert!(*waits.last().unwrap() >= 1);
+        assert_eq!(
+            Mode::Endless.fall_wait(wait::FALL, SURVIVAL_RAMP),
+            wait::FALL
+        );
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for mode in MODES {
+            assert_eq!(Mode::from_name(mode.name()), Some(mode));
+        }
+        assert_eq!(time_text(83_456), "1:23.45");
+    }
+}
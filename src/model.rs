@@ -1,22 +1,28 @@
 use rand::prelude::*;
+use rand_chacha::ChaCha12Rng;
+use std::rc::Rc;
 use std::time;
 
-use self::field::{FLOORWID, HEI};
+use crate::course::Course;
+use crate::floor::{FloorGenerator, Floors};
+use crate::mode::Mode;
 
 pub const CHAR: i32 = 16;
 pub const HARI_PER_FLOOR: i32 = 30; // 30%
 pub const ITEM_PERCENT: i32 = 15;
 pub const MUTEKI_TIME: i32 = 4000; // 4sec (length of MUTEKI bgm)
 pub const HIGHSCORES: i32 = 10;
+pub const ENEMY_PERCENT: i32 = 20; // 針でない床に敵が乗っている確率
+pub const MAX_PLAYERS: usize = 2; // 操作を割り当てられる人数。全部のGameのプレイヤーを合わせた数
 
 pub mod field {
-    pub const WID: i32 = 18; // フィールド幅（壁を含まない。セル数）
-    pub const HEI: i32 = 30; // フィールド高さ（セル数）
+    pub const WID: i32 = 18; // フィールド幅の初期値（壁を含まない。セル数）
+    pub const HEI: i32 = 30; // フィールド高さの初期値（セル数）
     pub const LEFT: i32 = super::CHAR * 1; //
-    pub const RIGHT: i32 = LEFT + (super::CHAR * WID) - 1; // -1している理由不明
     pub const TOP: i32 = 0;
     // pub const BOTTOM: i32 = TOP + (super::CHAR * HEI);
     pub const FLOORWID: i32 = 5; // 1個の床のセル数
+    pub const CEILING: i32 = 1; // 天井の針に刺さるhitoのy座標
 }
 
 pub mod wait {
@@ -30,6 +36,7 @@ pub mod wait {
     pub const MUTEKIFLASH: i32 = 80;
     pub const GAUGEFLASH: i32 = 60;
     pub const HARIBREAK: i32 = 140;
+    pub const ENEMY_WALK: i32 = 250;
     pub const GAMEOVER: i32 = 3400; // ms
                                     // pub const DEMO_TIME: i32 = 1000 * 60 * 3; // 5min
 }
@@ -41,6 +48,7 @@ pub enum Command {
     Right,
 }
 
+#[derive(Clone)]
 pub struct Hito {
     pub x: i32,
     pub y: i32,
@@ -50,31 +58,41 @@ pub struct Hito {
     pub para: bool,
     pub omori: bool,
     pub flashing: bool,
+    pub dead: bool,
+    pub deadtime: u32,
+    pub life: i32,
+    pub gauge: DamageGauge,
     pub mutekistart: u32,
     pub walktimer: Timer,
     pub flashtimer: Timer,
     pub wavetimer: Timer,
     pub mutekiflashtimer: Timer,
     pub haribreaktimer: Timer,
+    pub gameovertimer: Timer,
 }
 
 impl Hito {
-    pub fn new() -> Hito {
+    pub fn new(x: i32, y: i32) -> Hito {
         Hito {
-            x: field::WID / 2 - 1,
-            y: field::HEI / 2,
+            x,
+            y,
             hitonum: 0,
             hide: false,
             muteki: false,
             para: false,
             omori: false,
             flashing: false,
+            dead: false,
+            deadtime: 0,
+            life: 100,
+            gauge: DamageGauge::new(),
             mutekistart: 0,
             walktimer: Timer::new(wait::WALK),
             flashtimer: Timer::new(wait::HITOFLASH),
             wavetimer: Timer::new(wait::HITOWAVE),
             mutekiflashtimer: Timer::new(wait::MUTEKIFLASH),
             haribreaktimer: Timer::new(wait::HARIBREAK),
+            gameovertimer: Timer::new(wait::GAMEOVER),
         }
     }
 
@@ -88,6 +106,30 @@ impl Hito {
     }
 }
 
+// 床の上を往復し、触れるとダメージを受ける敵
+#[derive(Clone)]
+pub struct Enemy {
+    pub x: i32,
+    pub y: i32,
+    pub dir: i32, // -1:left 1:right
+    pub anime: i32,
+    pub walktimer: Timer,
+    pub dead: bool,
+}
+
+impl Enemy {
+    pub fn new(x: i32, y: i32, dir: i32) -> Enemy {
+        Enemy {
+            x,
+            y,
+            dir,
+            anime: 0,
+            walktimer: Timer::new(wait::ENEMY_WALK),
+            dead: false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Chara {
     EMPTY,
@@ -106,12 +148,42 @@ pub enum Chara {
     // HITOMUTEKI,
 }
 
+impl Chara {
+    // ファイルに書くときの1文字
+    pub fn to_char(self) -> char {
+        match self {
+            Chara::EMPTY => '.',
+            Chara::BLOCK => '=',
+            Chara::HARI => '^',
+            Chara::STAR => '*',
+            Chara::PARA => 'P',
+            Chara::OMORI => 'O',
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Chara> {
+        match c {
+            '.' => Some(Chara::EMPTY),
+            '=' => Some(Chara::BLOCK),
+            '^' => Some(Chara::HARI),
+            '*' => Some(Chara::STAR),
+            'P' => Some(Chara::PARA),
+            'O' => Some(Chara::OMORI),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
 pub enum EffectType {
     BREAK, // 無敵＆重りで床を破壊したときのエフェクト
     PANG,  // パラシュートで針の上に着地したときのエフェクト
+    #[allow(clippy::upper_case_acronyms)] // 元からある名前に合わせる
+    CRUSH, // 敵を倒したときのエフェクト
            // PTS,   // 未使用。床を破壊したときに10pt加算する構想だった模様
 }
 
+#[derive(Clone)]
 pub struct Effect {
     pub x: i32,
     pub y: i32,
@@ -136,18 +208,36 @@ impl Effect {
 
 pub const STATES_BREAK: i32 = 3;
 pub const STATES_PANG: i32 = 3;
+pub const STATES_CRUSH: i32 = 3;
 // pub const STATES_PTS: i32 = 7;
 
+// 規定時間に達したあと、余った時間をどう扱うか
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TimerMode {
+    // 元のDown!!と同じ。溜まった分を全部発火し終えたら余りを捨てて0から数え直す
+    // 例えば規定値100に250溜まると2回発火し、残りの50は捨てる
+    Reset,
+    // 余りを次に持ち越す。長い目で見ると経過時間/規定値の回数だけ発火する
+    Carry,
+}
+
+#[derive(Clone)]
 pub struct Timer {
     waittime: i32,
     wait: i32,
+    mode: TimerMode,
 }
 
 impl Timer {
     pub fn new(waittime: i32) -> Timer {
+        Timer::with_mode(waittime, TimerMode::Reset)
+    }
+
+    pub fn with_mode(waittime: i32, mode: TimerMode) -> Timer {
         let mut timer = Timer {
             waittime: 0,
             wait: 0,
+            mode,
         };
         timer.set_wait(waittime);
         timer.reset();
@@ -165,7 +255,7 @@ impl Timer {
     pub fn is_reached(&mut self) -> bool {
         if self.wait >= self.waittime {
             self.wait -= self.waittime;
-            if self.wait < self.waittime {
+            if self.wait < self.waittime && self.mode == TimerMode::Reset {
                 self.reset();
             }
             return true;
@@ -177,6 +267,17 @@ impl Timer {
         assert!(t > 0);
         self.waittime = t;
     }
+
+    // スナップショット用に、規定値・溜まった時間・モードを取り出す/戻す
+    pub fn state(&self) -> (i32, i32, TimerMode) {
+        (self.waittime, self.wait, self.mode)
+    }
+
+    pub fn from_state(waittime: i32, wait: i32, mode: TimerMode) -> Timer {
+        let mut timer = Timer::with_mode(waittime, mode);
+        timer.wait = wait;
+        timer
+    }
 }
 
 // Timerが規定値に達した場合に$blockを実行する
@@ -189,6 +290,7 @@ macro_rules! wait {
     };
 }
 
+#[derive(Clone)]
 pub struct DamageGauge {
     pub damagetimer: Timer,
     pub flashtimer: Timer,
@@ -209,6 +311,7 @@ impl DamageGauge {
     }
 }
 
+#[derive(Clone)]
 pub struct System {
     pub time: u32,
     pub count: i32,
@@ -235,215 +338,360 @@ impl System {
     }
 }
 
+// クラシックから追加したルールのON/OFF
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Rules {
+    pub enemies: bool,
+    pub ceiling: bool, // 床に乗っていると天井まで運ばれる
+    pub wid: i32,
+    pub hei: i32,
+    pub players: usize, // 同じフィールドで遊ぶ人数
+}
+
+impl Rules {
+    pub fn new() -> Rules {
+        Rules {
+            enemies: false,
+            ceiling: false,
+            wid: field::WID,
+            hei: field::HEI,
+            players: 1,
+        }
+    }
+
+    // Game::newで作れる組み合わせか。ファイルから読んだルールを確かめるのに使う
+    pub fn is_valid(&self) -> bool {
+        self.wid >= field::FLOORWID
+            && self.hei > field::CEILING + 2
+            && self.players >= 1
+            && self.players as i32 <= self.wid
+    }
+}
+
+// FNV-1aハッシュ。実行環境によらず同じ値になるので、ファイルに書いて比べられる
+pub struct Fnv(u64);
+
+impl Fnv {
+    pub fn new() -> Fnv {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    pub fn add(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// 起動時刻から乱数の種を作る
+pub fn time_seed() -> u64 {
+    let now = time::SystemTime::now();
+    now.duration_since(time::UNIX_EPOCH)
+        .expect("SystemTime before UNIX EPOCH!")
+        .as_secs()
+}
+
+#[derive(Clone)]
 pub struct Game {
-    pub rng: StdRng,
-    pub is_over: bool,
-    pub life: i32,
+    pub rng: ChaCha12Rng, // StdRngの中身と同じ。状態を保存できるように直接使う
+    pub rules: Rules,
+    pub is_over: bool, // 全員が倒れたか、クリアした
     pub requested_sounds: Vec<&'static str>,
     pub requested_musics: Vec<&'static str>,
-    pub hito: Hito,
-    pub isfloor: bool,
-    pub data: [[Chara; field::WID as usize]; field::HEI as usize],
+    pub hitos: Vec<Hito>,
+    pub isfloor: bool, // 次の行に床を置くか
+    pub wid: i32,
+    pub hei: i32,
+    pub data: Vec<Vec<Chara>>, // data[y][x]
     pub effects: Vec<Effect>,
+    pub enemies: Vec<Enemy>,
     pub score: i32,
     pub highscore: Vec<i32>,
+    pub ranked: bool, // falseなら終わってもハイスコアに載せない(練習モード)
+    pub mode: Mode,
+    pub cleartime: Option<u32>,      // クリアしたときのnow
+    pub floors: Floors,              // 床の作り方
+    pub course: Option<Rc<Course>>,  // Someなら床をランダムに作らず、コースの通りに並べる
+    pub field_log: Option<Vec<u64>>, // Someならスクロールするたびにfield_hashを記録する(テスト用)
     pub falltimer: Timer,
-    pub gameovertimer: Timer,
-    pub gauge: DamageGauge,
     pub now: u32,
     pub system: System,
 }
 
 impl Game {
-    pub fn new() -> Self {
-        let now = time::SystemTime::now();
-        let timestamp = now
-            .duration_since(time::UNIX_EPOCH)
-            .expect("SystemTime before UNIX EPOCH!")
-            .as_secs();
-        let rng = StdRng::seed_from_u64(timestamp);
-        println!("random seed = {}", timestamp);
-        // let rng = StdRng::seed_from_u64(0);
+    pub fn new(rules: Rules, seed: u64) -> Self {
+        println!("random seed = {}", seed);
+        Game::with_rng(rules, ChaCha12Rng::seed_from_u64(seed))
+    }
+
+    // 乱数の状態を外から渡して作る。保存した状態から戻すときに使う
+    pub fn with_rng(rules: Rules, rng: ChaCha12Rng) -> Self {
+        assert!(rules.is_valid());
 
         let mut game = Game {
             rng: rng,
+            rules,
             is_over: false,
-            life: 100,
             requested_sounds: Vec::new(),
             requested_musics: Vec::new(),
-            hito: Hito::new(),
-            isfloor: false,
-            data: [[Chara::EMPTY; field::WID as usize]; field::HEI as usize],
+            hitos: (0..rules.players)
+                .map(|i| {
+                    let x = rules.wid * (i as i32 + 1) / (rules.players as i32 + 1) - 1;
+                    Hito::new(x, rules.hei / 2)
+                })
+                .collect(),
+            isfloor: true,
+            wid: rules.wid,
+            hei: rules.hei,
+            data: vec![vec![Chara::EMPTY; rules.wid as usize]; rules.hei as usize],
             effects: Vec::new(),
+            enemies: Vec::new(),
             score: 0,
             highscore: Vec::new(),
+            ranked: true,
+            mode: Mode::Endless,
+            cleartime: None,
+            floors: Floors::Classic,
+            course: None,
+            field_log: None,
             falltimer: Timer::new(wait::FALL),
-            gameovertimer: Timer::new(wait::GAMEOVER),
-            gauge: DamageGauge::new(),
             now: 0,
             system: System::new(),
         };
 
         // 最初の床を生成
-        game.generate_floor();
+        game.next_floor(0);
 
         game
     }
 
-    pub fn generate_floor(&mut self) -> (i32, Chara) {
-        let mut pos = self.rand(field::WID + field::FLOORWID) - field::FLOORWID;
-        if pos < 0 {
-            pos = 0;
-        }
-        if pos > field::WID - field::FLOORWID {
-            pos = field::WID - field::FLOORWID;
+    // 床の作り方を変える。最初の行もその作り方で作り直す
+    pub fn set_floors(&mut self, floors: Floors) {
+        if self.floors != floors {
+            self.floors = floors;
+            self.regenerate_first_floor();
         }
+    }
 
-        let _type;
-        if self.rand(100) <= HARI_PER_FLOOR {
-            // randを<=で比較しているのはバグで、正しくは<だと思う
-            _type = Chara::HARI;
-        } else {
-            _type = Chara::BLOCK;
-        }
+    // ランダムな床の代わりにコースを使う。最初の行もコースのものに置き換える
+    pub fn set_course(&mut self, course: Rc<Course>) {
+        self.course = Some(course);
+        self.mode = Mode::Course;
+        self.regenerate_first_floor();
+    }
 
-        for i in 0..field::FLOORWID {
-            self.data[(field::HEI - 1) as usize][(pos + i) as usize] = _type;
+    fn regenerate_first_floor(&mut self) {
+        let y = (self.hei - 1) as usize;
+        self.data[y] = vec![Chara::EMPTY; self.wid as usize];
+        self.isfloor = true;
+        self.next_floor(0);
+    }
+
+    // このスコアになったらクリア
+    pub fn goal(&self) -> Option<i32> {
+        match &self.course {
+            Some(course) => Some(course.goal(self.hei)),
+            None => self.mode.goal(),
         }
+    }
 
-        return (pos, _type);
+    // 一番下の行にrow行目の床を置く。敵を乗せてよい床を置いたらその左端を返す
+    fn next_floor(&mut self, row: usize) -> Option<i32> {
+        let floor = match self.course.clone() {
+            Some(course) => course.generate(self, row),
+            None => self.floors.generator().generate(self, row),
+        };
+        self.isfloor = !self.isfloor;
+        floor
     }
 
-    pub fn update(&mut self, command: Command, dt: u32) {
+    pub fn update(&mut self, commands: &[Command], dt: u32) {
+        // クリアしたらその時の様子のまま止める
+        if self.cleartime.is_some() {
+            return;
+        }
         self.now += dt;
 
-        self.update_hito(command, dt);
-        self.update_damage(dt);
+        for i in 0..self.hitos.len() {
+            let command = commands.get(i).copied().unwrap_or(Command::None);
+            self.update_hito(i, command, dt);
+        }
+        self.update_enemies(dt);
+        for i in 0..self.hitos.len() {
+            self.update_damage(i, dt);
+        }
         self.update_effects(dt);
 
-        if self.is_over {
-            wait!(self.gameovertimer, dt, {
-                if !self.hito.hide {
-                    self.hito.hide = true;
-                    self.add_highscore();
+        let mut hidden = false;
+        for hito in self.hitos.iter_mut().filter(|hito| hito.dead) {
+            wait!(hito.gameovertimer, dt, {
+                if !hito.hide {
+                    hito.hide = true;
+                    hidden = true;
                 }
             });
+        }
+
+        if self.is_over {
+            if hidden
+                && self.ranked
+                && self.mode.ranks_by_score()
+                && self.hitos.iter().all(|hito| hito.hide)
+            {
+                self.add_highscore();
+            }
             return;
         }
 
+        // 時間で速くなるモードでは毎フレーム間隔を決め直す
+        if self.mode.ramps() {
+            self.update_scroll_wait();
+        }
         wait!(self.falltimer, dt, {
             self.scroll();
         });
+        if self.goal().is_some_and(|goal| self.score >= goal) {
+            self.cleartime = Some(self.now);
+            self.is_over = true;
+            self.requested_musics.push("halt");
+            return;
+        }
 
-        if self.life <= 0 {
+        let mut died = false;
+        for hito in self.hitos.iter_mut().filter(|hito| !hito.dead) {
+            if hito.life <= 0 {
+                hito.dead = true;
+                hito.deadtime = self.now;
+                hito.start_flashing();
+                self.requested_sounds.push("gameover.wav");
+                died = true;
+            }
+        }
+        // 倒れたプレイヤーのパラシュートや重りで落ちる速さが決まったままにしない
+        if died {
+            self.update_scroll_wait();
+        }
+        if self.hitos.iter().all(|hito| hito.dead) {
             self.is_over = true;
             self.requested_musics.push("halt");
-            self.requested_sounds.push("gameover.wav");
-            self.hito.start_flashing();
             return;
         }
 
         self.system.count_fps(dt);
     }
 
-    pub fn update_hito(&mut self, command: Command, dt: u32) {
-        if !self.is_over {
-            wait!(self.hito.walktimer, dt, {
+    pub fn update_hito(&mut self, i: usize, command: Command, dt: u32) {
+        if !self.hitos[i].dead {
+            wait!(self.hitos[i].walktimer, dt, {
                 // move
+                let (x, y) = (self.hitos[i].x, self.hitos[i].y);
                 if command == Command::Left {
-                    if self.hito.x > 0 && self.can_pass(self.hito.x - 1, self.hito.y) {
-                        self.hito.x -= 1;
+                    if x > 0 && self.can_pass(x - 1, y) && !self.is_occupied(x - 1, y, i) {
+                        self.hitos[i].x -= 1;
                     }
                 } else if command == Command::Right {
-                    if self.hito.x < field::WID - 1 && self.can_pass(self.hito.x + 1, self.hito.y) {
-                        self.hito.x += 1;
+                    if x < self.wid - 1 && self.can_pass(x + 1, y) && !self.is_occupied(x + 1, y, i)
+                    {
+                        self.hitos[i].x += 1;
                     }
                 }
             });
 
+            let (x, y) = (self.hitos[i].x as usize, self.hitos[i].y as usize);
+
             // get item
-            match self.data[self.hito.y as usize][self.hito.x as usize] {
+            match self.data[y][x] {
                 Chara::STAR => {
-                    self.data[self.hito.y as usize][self.hito.x as usize] = Chara::EMPTY;
-                    self.hito.muteki = true;
-                    self.hito.mutekistart = self.now;
-                    self.requested_musics.push("pause");
+                    self.data[y][x] = Chara::EMPTY;
+                    if !self.is_any_muteki() {
+                        self.requested_musics.push("pause");
+                    }
+                    self.hitos[i].muteki = true;
+                    self.hitos[i].mutekistart = self.now;
                     self.requested_sounds.push("muteki.wav");
                 }
                 Chara::PARA => {
-                    self.data[self.hito.y as usize][self.hito.x as usize] = Chara::EMPTY;
-                    self.set_scroll_wait(wait::FALL_PARA);
-                    self.hito.para = true;
-                    self.hito.omori = false;
+                    self.data[y][x] = Chara::EMPTY;
+                    self.hitos[i].para = true;
+                    self.hitos[i].omori = false;
+                    self.update_scroll_wait();
                     self.requested_sounds.push("getpara.wav");
                 }
                 Chara::OMORI => {
-                    self.data[self.hito.y as usize][self.hito.x as usize] = Chara::EMPTY;
-                    self.set_scroll_wait(wait::FALL_OMORI);
-                    self.hito.omori = true;
-                    self.hito.para = false;
+                    self.data[y][x] = Chara::EMPTY;
+                    self.hitos[i].omori = true;
+                    self.hitos[i].para = false;
+                    self.update_scroll_wait();
                     self.requested_sounds.push("getomori.wav");
                 }
                 _ => {}
             }
 
+            let hito = &mut self.hitos[i];
+
             // stop omori
-            if self.hito.omori
-                && self.hito.muteki
-                && ((self.now - self.hito.mutekistart) as f32 >= MUTEKI_TIME as f32 * 0.8)
+            if hito.omori
+                && hito.muteki
+                && ((self.now - hito.mutekistart) as f32 >= MUTEKI_TIME as f32 * 0.8)
             {
-                self.hito.omori = false;
-                self.set_scroll_wait(wait::FALL);
+                hito.omori = false;
+                self.update_scroll_wait();
             }
 
             // stop muteki
-            if self.hito.muteki && ((self.now - self.hito.mutekistart) as f32 >= MUTEKI_TIME as f32)
-            {
-                self.hito.muteki = false;
-                self.hito.hitonum = 0;
-                self.requested_musics.push("resume");
+            let hito = &mut self.hitos[i];
+            if hito.muteki && ((self.now - hito.mutekistart) as f32 >= MUTEKI_TIME as f32) {
+                hito.muteki = false;
+                hito.hitonum = 0;
+                if !self.is_any_muteki() {
+                    self.requested_musics.push("resume");
+                }
             }
 
             // stop para
-            if self.hito.para
-                && self.data[(self.hito.y + 1) as usize][self.hito.x as usize] == Chara::HARI
-                && !self.hito.muteki
-            {
-                self.hito.para = false;
-                self.set_scroll_wait(wait::FALL);
+            let hito = &mut self.hitos[i];
+            if hito.para && self.data[y + 1][x] == Chara::HARI && !hito.muteki {
+                hito.para = false;
+                self.update_scroll_wait();
                 self.requested_sounds.push("spank.wav");
                 self.effects.push(Effect::new(
-                    self.hito.x,
-                    self.hito.y,
+                    x as i32,
+                    y as i32,
                     EffectType::PANG,
                     Timer::new(150),
                 ));
             }
 
             // break!
-            if self.hito.omori && self.hito.muteki {
-                if self.data[(self.hito.y + 1) as usize][self.hito.x as usize] == Chara::BLOCK {
-                    self.field_break(self.hito.x, self.hito.y + 1);
-                } else if self.data[(self.hito.y + 1) as usize][self.hito.x as usize] == Chara::HARI
-                {
-                    wait!(self.hito.haribreaktimer, dt, {
-                        self.field_break(self.hito.x, self.hito.y + 1);
+            if self.hitos[i].omori && self.hitos[i].muteki {
+                if self.data[y + 1][x] == Chara::BLOCK {
+                    self.field_break(x as i32, y as i32 + 1);
+                } else if self.data[y + 1][x] == Chara::HARI {
+                    wait!(self.hitos[i].haribreaktimer, dt, {
+                        self.field_break(x as i32, y as i32 + 1);
                     });
                 }
             }
         }
 
-        if self.hito.flashing {
-            wait!(self.hito.flashtimer, dt, {
-                self.hito.hitonum = 1 - self.hito.hitonum; // 0:white 1:red
+        let hito = &mut self.hitos[i];
+
+        if hito.flashing {
+            wait!(hito.flashtimer, dt, {
+                hito.hitonum = 1 - hito.hitonum; // 0:white 1:red
             });
         }
 
-        if self.hito.muteki {
-            wait!(self.hito.mutekiflashtimer, dt, {
-                self.hito.hitonum += 1;
-                if self.hito.hitonum > 6 {
-                    self.hito.hitonum = 0;
+        if hito.muteki {
+            wait!(hito.mutekiflashtimer, dt, {
+                hito.hitonum += 1;
+                if hito.hitonum > 6 {
+                    hito.hitonum = 0;
                 }
             });
         }
@@ -460,35 +708,137 @@ impl Game {
         self.falltimer.set_wait(wait);
     }
 
-    pub fn update_damage(&mut self, dt: u32) {
-        if self.data[(self.hito.y + 1) as usize][self.hito.x as usize] == Chara::HARI
-            && !self.hito.muteki
-        {
-            // damage start
-            if self.gauge.damaging == false {
-                self.gauge.damaging = true;
-                self.hito.start_flashing();
-                self.gauge.flashing = true;
+    // 落下速度はフィールドを共有する全員の持っているアイテムで決まる
+    pub fn update_scroll_wait(&mut self) {
+        let alive = self.hitos.iter().filter(|hito| !hito.dead);
+        let wait = if alive.clone().any(|hito| hito.omori) {
+            wait::FALL_OMORI
+        } else if alive.clone().any(|hito| hito.para) {
+            wait::FALL_PARA
+        } else {
+            wait::FALL
+        };
+        self.set_scroll_wait(self.mode.fall_wait(wait, self.now));
+    }
+
+    // 倒れたプレイヤーの無敵は終わらないので数えない
+    pub fn is_any_muteki(&self) -> bool {
+        self.hitos.iter().any(|hito| !hito.dead && hito.muteki)
+    }
+
+    // (x, y)にi番目以外の生きているプレイヤーがいるか
+    pub fn is_occupied(&self, x: i32, y: i32, i: usize) -> bool {
+        self.hitos
+            .iter()
+            .enumerate()
+            .any(|(j, hito)| j != i && !hito.dead && hito.x == x && hito.y == y)
+    }
+
+    pub fn update_enemies(&mut self, dt: u32) {
+        for i in 0..self.enemies.len() {
+            if !self.is_over {
+                wait!(self.enemies[i].walktimer, dt, {
+                    self.walk_enemy(i);
+                });
             }
 
-            wait!(self.gauge.damagetimer, dt, {
-                if !self.is_over {
-                    self.requested_sounds.push("damage.wav");
+            for hito in self.hitos.iter().filter(|hito| !hito.dead) {
+                let enemy = &self.enemies[i];
+                if enemy.dead {
+                    break;
                 }
-                self.life -= 1;
+                // 無敵中に触れるか、重りを持って上から落ちてくると倒せる
+                let touching = enemy.x == hito.x && enemy.y == hito.y;
+                let stomped = hito.omori && enemy.x == hito.x && enemy.y == hito.y + 1;
+                if (touching && hito.muteki) || stomped {
+                    let enemy = &mut self.enemies[i];
+                    enemy.dead = true;
+                    self.effects.push(Effect::new(
+                        enemy.x,
+                        enemy.y,
+                        EffectType::CRUSH,
+                        Timer::new(150),
+                    ));
+                    self.requested_sounds.push("break.wav");
+                }
+            }
+        }
+        self.enemies.retain(|enemy| !enemy.dead);
+    }
+
+    fn walk_enemy(&mut self, i: usize) {
+        let (x, y, dir) = {
+            let enemy = &self.enemies[i];
+            (enemy.x, enemy.y, enemy.dir)
+        };
+
+        // 足場が壊されたら落ちる
+        if self.can_pass(x, y + 1) {
+            if y + 1 >= self.hei - 1 {
+                self.enemies[i].dead = true;
+            } else {
+                self.enemies[i].y += 1;
+            }
+            return;
+        }
+
+        // 床の端か障害物で折り返す
+        let nx = x + dir;
+        let turn =
+            !(0..self.wid).contains(&nx) || !self.can_pass(nx, y) || self.can_pass(nx, y + 1);
+        let enemy = &mut self.enemies[i];
+        if turn {
+            enemy.dir = -dir;
+        } else {
+            enemy.x = nx;
+        }
+        enemy.anime = 1 - enemy.anime;
+    }
+
+    pub fn is_touching_enemy(&self, i: usize) -> bool {
+        let hito = &self.hitos[i];
+        self.enemies
+            .iter()
+            .any(|enemy| !enemy.dead && enemy.x == hito.x && enemy.y == hito.y)
+    }
+
+    pub fn is_touching_ceiling(&self, i: usize) -> bool {
+        self.rules.ceiling && self.hitos[i].y <= field::CEILING
+    }
+
+    pub fn update_damage(&mut self, i: usize, dt: u32) {
+        if self.hitos[i].dead {
+            return;
+        }
+        let (x, y) = (self.hitos[i].x as usize, self.hitos[i].y as usize);
+        let hurt = self.data[y + 1][x] == Chara::HARI
+            || self.is_touching_enemy(i)
+            || self.is_touching_ceiling(i);
+        let hito = &mut self.hitos[i];
+        if hurt && !hito.muteki {
+            // damage start
+            if hito.gauge.damaging == false {
+                hito.gauge.damaging = true;
+                hito.start_flashing();
+                hito.gauge.flashing = true;
+            }
+
+            wait!(hito.gauge.damagetimer, dt, {
+                self.requested_sounds.push("damage.wav");
+                hito.life -= 1;
             });
 
-            wait!(self.gauge.flashtimer, dt, {
-                self.gauge.is_red = !self.gauge.is_red;
+            wait!(hito.gauge.flashtimer, dt, {
+                hito.gauge.is_red = !hito.gauge.is_red;
             });
         } else {
             // damage stop
-            if self.gauge.damaging {
-                self.gauge.damaging = false;
-                self.hito.stop_flashing();
-                self.gauge.flashing = false;
-                self.gauge.is_red = false;
-                self.gauge.damagetimer.reset();
+            if hito.gauge.damaging {
+                hito.gauge.damaging = false;
+                hito.stop_flashing();
+                hito.gauge.flashing = false;
+                hito.gauge.is_red = false;
+                hito.gauge.damagetimer.reset();
             }
         }
     }
@@ -511,6 +861,14 @@ impl Game {
                             effect.dead = true;
                         }
                     });
+                }
+                EffectType::CRUSH => {
+                    wait!(effect.timer, dt, {
+                        effect.state += 1;
+                        if effect.state >= STATES_CRUSH {
+                            effect.dead = true;
+                        }
+                    });
                 } // EffectType::PTS => {
                   //     wait!(effect.timer, dt, {
                   //         effect.state += 1;
@@ -524,51 +882,113 @@ impl Game {
         self.effects.retain(|effect| !effect.dead);
     }
 
+    // i番目のプレイヤーの足元が空いているか
+    pub fn can_fall(&self, i: usize) -> bool {
+        let hito = &self.hitos[i];
+        self.can_pass(hito.x, hito.y + 1) && !self.is_occupied(hito.x, hito.y + 1, i)
+    }
+
+    // 天井ルールで、i番目のプレイヤーを床ごと上へ運べるか
+    pub fn can_carry(&self, i: usize) -> bool {
+        self.rules.ceiling && self.hitos[i].y > field::CEILING
+    }
+
     pub fn scroll(&mut self) -> bool {
-        if !self.can_pass(self.hito.x, self.hito.y + 1) {
-            return false;
+        let alive: Vec<usize> = (0..self.hitos.len())
+            .filter(|i| !self.hitos[*i].dead)
+            .collect();
+
+        // 床に乗っていて、天井へ運ぶこともできないプレイヤーがいればスクロールしない
+        let stuck = alive
+            .iter()
+            .any(|&i| !self.can_fall(i) && !self.can_carry(i));
+        // 運ばれて上にいるプレイヤーがいれば、元の高さに戻るまで待つ
+        let rising = self.rules.ceiling
+            && alive
+                .iter()
+                .any(|&i| self.can_fall(i) && self.hitos[i].y < self.hei / 2);
+
+        if stuck || rising {
+            // スクロールしない間、足元が空いているプレイヤーはフィールド内を落ちる
+            let mut moved = false;
+            for &i in &alive {
+                let y = self.hitos[i].y;
+                if self.can_fall(i) && (stuck || y < self.hei / 2) && y < self.hei - 2 {
+                    self.hitos[i].y += 1;
+                    self.land(i);
+                    moved = true;
+                }
+            }
+            return moved;
         }
-        for i in 0..(field::HEI - 1) {
-            self.data[i as usize] = self.data[(i + 1) as usize];
+
+        // 床に乗ったまま天井へ運ばれる
+        let carried: Vec<usize> = alive
+            .iter()
+            .copied()
+            .filter(|&i| !self.can_fall(i))
+            .collect();
+
+        self.scroll_field();
+        for &i in &alive {
+            if carried.contains(&i) {
+                self.hitos[i].y -= 1;
+            } else {
+                self.land(i);
+            }
         }
 
-        for i in 0..field::WID {
-            self.data[(field::HEI - 1) as usize][i as usize] = Chara::EMPTY;
+        self.score += 1;
+
+        if let Some(mut log) = self.field_log.take() {
+            log.push(self.field_hash());
+            self.field_log = Some(log);
         }
 
-        self.effects_scroll();
+        return true;
+    }
 
-        // make new floor(if @isfloor) & item(if @isfloor&&rand)
-        if self.isfloor {
-            let (pos, _type) = self.generate_floor();
-
-            if self.rand(100) <= ITEM_PERCENT && _type != Chara::HARI {
-                let r = self.rand(100);
-                let item_type = if r <= 33 {
-                    Chara::STAR
-                } else if r <= 66 {
-                    Chara::PARA
-                } else {
-                    Chara::OMORI
-                };
-                let x = pos + (FLOORWID / 2);
-                let y = HEI - 2;
-                self.data[y as usize][x as usize] = item_type;
+    // 床・アイテム・敵の配置のハッシュ
+    pub fn field_hash(&self) -> u64 {
+        let mut hash = Fnv::new();
+        for row in &self.data {
+            for chara in row {
+                hash.add(&(*chara as i32).to_le_bytes());
             }
         }
+        for enemy in &self.enemies {
+            for value in [enemy.x, enemy.y, enemy.dir] {
+                hash.add(&value.to_le_bytes());
+            }
+        }
+        hash.finish()
+    }
 
-        // invert @isfloor
-        self.isfloor = !self.isfloor;
-
-        if !self.can_pass(self.hito.x, self.hito.y + 1) {
-            if self.data[(self.hito.y + 1) as usize][self.hito.x as usize] == Chara::BLOCK {
+    fn land(&mut self, i: usize) {
+        let hito = &self.hitos[i];
+        if !self.can_pass(hito.x, hito.y + 1) {
+            if self.data[(hito.y + 1) as usize][hito.x as usize] == Chara::BLOCK {
                 self.requested_sounds.push("foot.wav");
             }
         }
+    }
 
-        self.score += 1;
+    pub fn scroll_field(&mut self) {
+        self.data.remove(0);
+        self.data.push(vec![Chara::EMPTY; self.wid as usize]);
 
-        return true;
+        self.effects_scroll();
+        self.enemies_scroll();
+
+        // 床とアイテムは床の作り方に任せ、敵を乗せてよい床が来たら敵を出す
+        // スコアはスクロールした回数なので、新しい行はscore+1行目
+        if let Some(pos) = self.next_floor(self.score as usize + 1) {
+            if self.rules.enemies && self.rand(100) < ENEMY_PERCENT {
+                let x = pos + self.rand(field::FLOORWID);
+                let dir = if self.rand(2) == 0 { -1 } else { 1 };
+                self.enemies.push(Enemy::new(x, self.hei - 2, dir));
+            }
+        }
     }
 
     pub fn effects_scroll(&mut self) {
@@ -580,6 +1000,15 @@ impl Game {
         }
     }
 
+    pub fn enemies_scroll(&mut self) {
+        for enemy in &mut self.enemies {
+            enemy.y -= 1;
+            if enemy.y <= 0 {
+                enemy.dead = true;
+            }
+        }
+    }
+
     pub fn can_pass(&self, x: i32, y: i32) -> bool {
         match self.data[y as usize][x as usize] {
             Chara::EMPTY | Chara::STAR | Chara::PARA | Chara::OMORI => true,
@@ -602,3 +1031,130 @@ impl Game {
         self.rng.gen_range(0..max)
     }
 }
+
+// 全プレイヤーの操作を、それぞれのGameのプレイヤー数ずつに分けて渡す
+pub fn update_games(games: &mut [Game], commands: &[Command], dt: u32) {
+    let mut commands = commands.iter().copied();
+    for game in games {
+        let game_commands: Vec<Command> = commands.by_ref().take(game.hitos.len()).collect();
+        game.update(&game_commands, dt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CASES: u64 = 500;
+
+    // 乱数で規定値とdtの列を作る。0や規定値の何倍もの長いdtも混ぜる
+    fn random_case(rng: &mut StdRng) -> (i32, Vec<u32>) {
+        let waittime = rng.gen_range(1..200);
+        let len = rng.gen_range(0..100);
+        let dts = (0..len)
+            .map(|_| match rng.gen_range(0..10) {
+                0 => 0,
+                1 => rng.gen_range(0..waittime as u32 * 5),
+                _ => rng.gen_range(0..waittime as u32 * 2),
+            })
+            .collect();
+        (waittime, dts)
+    }
+
+    fn count_firings(timer: &mut Timer, dt: u32) -> i32 {
+        let mut count = 0;
+        wait!(timer, dt, {
+            count += 1;
+        });
+        count
+    }
+
+    #[test]
+    fn reset_mode_drops_leftover_after_catching_up() {
+        for seed in 0..CASES {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (waittime, dts) = random_case(&mut rng);
+            let mut timer = Timer::new(waittime);
+            let mut acc = 0;
+            for dt in dts {
+                acc += dt as i32;
+                let expected = acc / waittime;
+                if expected > 0 {
+                    acc = 0;
+                }
+                assert_eq!(count_firings(&mut timer, dt), expected, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn carry_mode_fires_once_per_period() {
+        for seed in 0..CASES {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (waittime, dts) = random_case(&mut rng);
+            let mut timer = Timer::with_mode(waittime, TimerMode::Carry);
+            let mut total = 0;
+            let mut firings = 0;
+            for dt in dts {
+                total += dt as i32;
+                firings += count_firings(&mut timer, dt);
+                assert_eq!(firings, total / waittime, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn reset_mode_never_fires_more_than_carry_mode() {
+        for seed in 0..CASES {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (waittime, dts) = random_case(&mut rng);
+            let mut reset = Timer::new(waittime);
+            let mut carry = Timer::with_mode(waittime, TimerMode::Carry);
+            let (mut reset_firings, mut carry_firings) = (0, 0);
+            for dt in dts {
+                reset_firings += count_firings(&mut reset, dt);
+                carry_firings += count_firings(&mut carry, dt);
+                assert!(reset_firings <= carry_firings, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn reset_restarts_counting() {
+        let mut timer = Timer::with_mode(100, TimerMode::Carry);
+        assert_eq!(count_firings(&mut timer, 150), 1);
+        timer.reset();
+        assert_eq!(count_firings(&mut timer, 99), 0);
+        assert_eq!(count_firings(&mut timer, 1), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_waittime_is_rejected() {
+        Timer::new(0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_wait_rejects_negative() {
+        let mut timer = Timer::new(100);
+        timer.set_wait(-1);
+    }
+
+    // ゴールに着いたらそのときの時間で止まり、それ以上は進まない
+    #[test]
+    fn game_stops_at_goal() {
+        let replay = crate::replay::Replay::load("tests/replays/enemies.replay").unwrap();
+        let mut game = Game::new(replay.rules, replay.seed);
+        game.mode = Mode::TimeAttack;
+        let goal = game.goal().unwrap();
+        game.score = goal - 20; // 20回スクロールしたら着く
+        for frame in &replay.frames {
+            game.update(&frame.commands, frame.dt);
+        }
+        assert!(game.is_over);
+        assert_eq!(game.score, goal);
+        assert_eq!(game.cleartime, Some(game.now));
+        assert!(game.hitos.iter().all(|hito| !hito.dead));
+    }
+}
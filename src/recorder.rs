@@ -0,0 +1,54 @@
+use std::fs;
+use std::io;
+
+pub const RECORD_FPS: u32 = 30;
+
+// リプレイを一定間隔で描いて連番の画像に書き出す(--headless --record)
+// 間隔はゲーム内の経過時間で測り、ウィンドウを使わずに描くので、画面やVSyncによらず毎回同じフレームになる
+// 書き出し先には画像と一緒に manifest.txt を置く
+//   fps = 30
+//   ---
+//   frame00000.bmp 0   <- ファイル名と、録画開始からのゲーム内時間(ms)
+pub struct Recorder {
+    pub dir: String,
+    time: u32, // 録画開始からのゲーム内時間
+    next: u32, // 次に書き出す時間
+    count: usize,
+    manifest: String,
+}
+
+impl Recorder {
+    pub fn start(dir: &str) -> io::Result<Recorder> {
+        fs::create_dir_all(dir)?;
+        Ok(Recorder {
+            dir: dir.to_string(),
+            time: 0,
+            next: 0,
+            count: 0,
+            manifest: format!("fps = {}\n---\n", RECORD_FPS),
+        })
+    }
+
+    // ゲームをdt進めたあとに呼び、書き出す時間になっていたら画像のパスを返す
+    // dtが長くて何フレーム分も飛んだときも書き出すのは1枚
+    pub fn advance(&mut self, dt: u32) -> Option<String> {
+        self.time += dt;
+        if self.time < self.next {
+            return None;
+        }
+        let period = 1000 / RECORD_FPS;
+        while self.next <= self.time {
+            self.next += period;
+        }
+        let name = format!("frame{:05}.bmp", self.count);
+        self.manifest += &format!("{} {}\n", name, self.time);
+        self.count += 1;
+        Some(format!("{}/{}", self.dir, name))
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        fs::write(format!("{}/manifest.txt", self.dir), &self.manifest)?;
+        println!("Recorded {} frames to {}", self.count, self.dir);
+        Ok(())
+    }
+}
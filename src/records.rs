@@ -0,0 +1,75 @@
+use std::io;
+
+use crate::lists;
+
+pub const RECORDS_FILE: &str = "./records.cfg";
+pub const RECORDS: usize = 10; // モードごとに残す数
+
+// スコア以外で競うモードの記録。モードごとに良い順に並べて残す
+//   timeattack = 83450 90210
+pub struct Records {
+    modes: Vec<(String, Vec<u32>)>,
+}
+
+impl Records {
+    // ファイルが無ければ記録なし
+    pub fn load(path: &str) -> Records {
+        Records {
+            modes: lists::load(path),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        lists::save(path, &self.modes)
+    }
+
+    pub fn get(&self, mode: &str) -> &[u32] {
+        lists::get(&self.modes, mode)
+    }
+
+    // 記録を加え、上位に入れば順位(0から)を返す。lowerがtrueなら小さいほど良い
+    pub fn add(&mut self, mode: &str, value: u32, lower: bool) -> Option<usize> {
+        let index = match self.modes.iter().position(|(m, _)| m == mode) {
+            Some(index) => index,
+            None => {
+                self.modes.push((mode.to_string(), Vec::new()));
+                self.modes.len() - 1
+            }
+        };
+        let values = &mut self.modes[index].1;
+        // 同じ値なら先に出した方を上にする
+        let rank = values
+            .iter()
+            .position(|v| if lower { value < *v } else { value > *v })
+            .unwrap_or(values.len());
+        if rank >= RECORDS {
+            return None;
+        }
+        values.insert(rank, value);
+        values.truncate(RECORDS);
+        Some(rank)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_kept_best_first() {
+        let mut records = Records { modes: Vec::new() };
+        assert_eq!(records.add("timeattack", 900, true), Some(0));
+        assert_eq!(records.add("timeattack", 500, true), Some(0));
+        assert_eq!(records.add("timeattack", 900, true), Some(2));
+        assert_eq!(records.add("survival", 500, false), Some(0));
+        assert_eq!(records.add("survival", 900, false), Some(0));
+        assert_eq!(records.get("timeattack"), [500, 900, 900]);
+        assert_eq!(records.get("survival"), [900, 500]);
+
+        for value in 0..RECORDS as u32 {
+            records.add("timeattack", value, true);
+        }
+        assert_eq!(records.get("timeattack").len(), RECORDS);
+        assert_eq!(records.add("timeattack", 1000, true), None);
+    }
+}
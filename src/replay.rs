@@ -0,0 +1,330 @@
+use std::fs;
+use std::io;
+use std::rc::Rc;
+
+use crate::course::Course;
+use crate::floor::Floors;
+use crate::mode::Mode;
+use crate::model::{Command, Game, Rules, MAX_PLAYERS};
+
+pub const REPLAY_FILE: &str = "./last.replay";
+
+// 1フレーム分の入力
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub dt: u32,
+    pub commands: Vec<Command>, // 全プレイヤー分
+}
+
+// Gameは種と入力が同じなら同じ結果になるので、種・ルールと毎フレームの入力だけを記録する
+//   seed = 1234
+//   wid = 18
+//   ...
+//   ---
+//   16 L.   <- 経過時間と、プレイヤーごとの操作(L, R, .)
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub seed: u64,
+    pub rules: Rules,
+    pub games: usize, // 並べるGameの数
+    pub versus: bool,
+    pub mode: Mode,                 // Endless以外のときだけ書く
+    pub floors: Floors,             // Classic以外のときだけ書く
+    pub course: Option<Rc<Course>>, // ファイルの場所とidを書き、読むときにコースも読む
+    pub frames: Vec<Frame>,
+}
+
+impl Replay {
+    pub fn new(seed: u64, rules: Rules, games: usize, versus: bool) -> Replay {
+        Replay {
+            seed,
+            rules,
+            games,
+            versus,
+            mode: Mode::Endless,
+            floors: Floors::Classic,
+            course: None,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn players(&self) -> usize {
+        self.games * self.rules.players
+    }
+
+    // 記録を始めたときと同じGameを作る
+    pub fn new_game(&self) -> Game {
+        let mut game = Game::new(self.rules, self.seed);
+        game.mode = self.mode;
+        game.set_floors(self.floors);
+        if let Some(course) = &self.course {
+            game.set_course(course.clone());
+        }
+        game
+    }
+
+    pub fn push(&mut self, dt: u32, commands: &[Command]) {
+        self.frames.push(Frame {
+            dt,
+            commands: commands.to_vec(),
+        });
+    }
+
+    pub fn load(path: &str) -> Result<Replay, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut lines = text.lines();
+        let mut replay = Replay::new(0, Rules::new(), 1, false);
+        let mut course_id = None;
+
+        for line in lines.by_ref() {
+            let line = line.trim();
+            if line == "---" {
+                break;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .map(|(name, value)| (name.trim(), value.trim()))
+                .ok_or_else(|| format!("{}: invalid line: {}", path, line))?;
+            let invalid = || format!("{}: invalid value: {}", path, line);
+            match name {
+                "seed" => replay.seed = value.parse().map_err(|_| invalid())?,
+                "enemies" => replay.rules.enemies = value.parse().map_err(|_| invalid())?,
+                "ceiling" => replay.rules.ceiling = value.parse().map_err(|_| invalid())?,
+                "wid" => replay.rules.wid = value.parse().map_err(|_| invalid())?,
+                "hei" => replay.rules.hei = value.parse().map_err(|_| invalid())?,
+                "players" => replay.rules.players = value.parse().map_err(|_| invalid())?,
+                "games" => replay.games = value.parse().map_err(|_| invalid())?,
+                "versus" => replay.versus = value.parse().map_err(|_| invalid())?,
+                "mode" => replay.mode = Mode::from_name(value).ok_or_else(invalid)?,
+                "floors" => replay.floors = Floors::from_name(value).ok_or_else(invalid)?,
+                "course" => replay.course = Some(Rc::new(Course::load(value)?)),
+                "course_id" => course_id = Some(value.to_string()),
+                _ => return Err(format!("{}: unknown setting: {}", path, name)),
+            }
+        }
+
+        // 壊れたファイルでGame::newを止めないよう、ここで確かめる
+        if !replay.rules.is_valid() {
+            return Err(format!("{}: invalid rules", path));
+        }
+        if replay.games < 1 || replay.players() > MAX_PLAYERS {
+            return Err(format!("{}: invalid number of games", path));
+        }
+        // コースはファイルから読み直すので、記録したときと同じコースでなければ再生がずれる
+        if let Some(course) = &replay.course {
+            if course_id.as_deref() != Some(course.id.as_str()) {
+                return Err(format!("{}: {} has changed", path, course.path));
+            }
+            if course.wid != replay.rules.wid {
+                return Err(format!("{}: the width differs from the course", path));
+            }
+        }
+
+        for line in lines {
+            let invalid = || format!("{}: invalid frame: {}", path, line);
+            let (dt, commands) = line.split_once(' ').ok_or_else(invalid)?;
+            let dt = dt.parse().map_err(|_| invalid())?;
+            let commands = commands
+                .chars()
+                .map(|c| match c {
+                    'L' => Ok(Command::Left),
+                    'R' => Ok(Command::Right),
+                    '.' => Ok(Command::None),
+                    _ => Err(invalid()),
+                })
+                .collect::<Result<Vec<Command>, String>>()?;
+            if commands.len() != replay.players() {
+                return Err(invalid());
+            }
+            replay.frames.push(Frame { dt, commands });
+        }
+
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut text = format!(
+            "seed = {}\n\
+             enemies = {}\n\
+             ceiling = {}\n\
+             wid = {}\n\
+             hei = {}\n\
+             players = {}\n\
+             games = {}\n\
+             versus = {}\n",
+            self.seed,
+            self.rules.enemies,
+            self.rules.ceiling,
+            self.rules.wid,
+            self.rules.hei,
+            self.rules.players,
+            self.games,
+            self.versus,
+        );
+        if self.mode != Mode::Endless {
+            text += &format!("mode = {}\n", self.mode.name());
+        }
+        if self.floors != Floors::Classic {
+            text += &format!("floors = {}\n", self.floors.name());
+        }
+        if let Some(course) = &self.course {
+            text += &format!("course = {}\ncourse_id = {}\n", course.path, course.id);
+        }
+        text += "---\n";
+        for frame in &self.frames {
+            let commands: String = frame
+                .commands
+                .iter()
+                .map(|command| match command {
+                    Command::Left => 'L',
+                    Command::Right => 'R',
+                    Command::None => '.',
+                })
+                .collect();
+            text += &format!("{} {}\n", frame.dt, commands);
+        }
+        fs::write(path, text)
+    }
+}
+
+// tests/replays の各リプレイを再生し、結果が同じ名前の .expected と一致することを確かめる
+// 挙動を意図して変えたときは UPDATE_REPLAYS=1 cargo test で .expected を作り直す
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::update_games;
+
+    const REPLAY_DIR: &str = "tests/replays";
+
+    // 最終的なスコア・ライフ・倒れた時間と、スクロールごとのフィールドのハッシュ
+    fn run(replay: &Replay) -> String {
+        let mut games: Vec<Game> = (0..replay.games)
+            .map(|_| {
+                let mut game = replay.new_game();
+                game.field_log = Some(Vec::new());
+                game
+            })
+            .collect();
+        for frame in &replay.frames {
+            update_games(&mut games, &frame.commands, frame.dt);
+        }
+
+        let mut text = String::new();
+        for (i, game) in games.iter().enumerate() {
+            let join = |f: &dyn Fn(&crate::model::Hito) -> String| {
+                game.hitos.iter().map(f).collect::<Vec<String>>().join(" ")
+            };
+            text += &format!("game {}\n", i + 1);
+            text += &format!("score = {}\n", game.score);
+            text += &format!("life = {}\n", join(&|hito| hito.life.to_string()));
+            text += &format!("deadtime = {}\n", join(&|hito| hito.deadtime.to_string()));
+            for (scroll, hash) in game.field_log.as_ref().unwrap().iter().enumerate() {
+                text += &format!("{} {:016x}\n", scroll + 1, hash);
+            }
+        }
+        text
+    }
+
+    // 範囲外のルールはGame::newに渡る前にエラーにする
+    #[test]
+    fn invalid_header_is_an_error() {
+        let path = std::env::temp_dir().join("rust-down-test.replay");
+        let path = path.to_str().unwrap();
+        for header in [
+            "wid = 2",
+            "players = 0",
+            "hei = 3",
+            "games = 0",
+            "games = 3",
+            "games = 2\nplayers = 2",
+        ] {
+            fs::write(path, format!("{}\n---\n16 .\n", header)).unwrap();
+            assert!(Replay::load(path).is_err(), "{}", header);
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    // 記録したときと違うコースでは再生しない
+    #[test]
+    fn changed_course_is_an_error() {
+        let dir = std::env::temp_dir();
+        let course_path = dir.join("rust-down-test-replay.course");
+        let course_path = course_path.to_str().unwrap();
+        let path = dir.join("rust-down-test-course.replay");
+        let path = path.to_str().unwrap();
+        fs::write(course_path, "=====.\n......\n").unwrap();
+        let course = Course::load(course_path).unwrap();
+        let mut rules = Rules::new();
+        rules.wid = course.wid;
+        let mut replay = Replay::new(1, rules, 1, false);
+        replay.mode = Mode::Course;
+        replay.course = Some(Rc::new(course));
+        replay.push(16, &[Command::None]);
+        replay.save(path).unwrap();
+        assert!(Replay::load(path).is_ok());
+
+        fs::write(course_path, ".=====\n......\n").unwrap();
+        assert!(Replay::load(path).is_err());
+
+        fs::write(course_path, "=====.\n......\n").unwrap();
+        let text = fs::read_to_string(path).unwrap();
+        fs::write(path, text.replace("wid = 6", "wid = 18")).unwrap();
+        assert!(Replay::load(path).is_err());
+        fs::remove_file(path).unwrap();
+        fs::remove_file(course_path).unwrap();
+    }
+
+    #[test]
+    fn replays_match_expected() {
+        let mut paths: Vec<_> = fs::read_dir(REPLAY_DIR)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "replay"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty(), "no replays in {}", REPLAY_DIR);
+
+        let mut failures = Vec::new();
+        for path in paths {
+            let replay = Replay::load(path.to_str().unwrap()).unwrap();
+            let actual = run(&replay);
+            let expected_path = path.with_extension("expected");
+            if std::env::var("UPDATE_REPLAYS").is_ok() {
+                fs::write(&expected_path, &actual).unwrap();
+                println!("wrote {}", expected_path.display());
+                continue;
+            }
+            if !expected_path.exists() {
+                failures.push(format!(
+                    "missing {}, rerun with UPDATE_REPLAYS=1",
+                    expected_path.display()
+                ));
+                continue;
+            }
+            let expected = fs::read_to_string(&expected_path).unwrap();
+            // 最初に食い違った行を報告する
+            if let Some((line, (e, a))) = expected
+                .lines()
+                .zip(actual.lines())
+                .enumerate()
+                .find(|(_, (e, a))| e != a)
+            {
+                failures.push(format!(
+                    "{}:{}: expected `{}`, got `{}`",
+                    expected_path.display(),
+                    line + 1,
+                    e,
+                    a
+                ));
+            } else if expected.lines().count() != actual.lines().count() {
+                failures.push(format!(
+                    "{}: expected {} lines, got {}",
+                    expected_path.display(),
+                    expected.lines().count(),
+                    actual.lines().count()
+                ));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+
+use crate::model::{Game, Timer, TimerMode};
+
+pub const REWIND_INTERVAL: i32 = 100; // 状態を残す間隔(ゲーム内時間のms)
+pub const REWIND_STATES: usize = 50; // 残す数。50 * 100ms で5秒戻れる
+
+// 練習モードの巻き戻し
+// 一定間隔でGameを丸ごと複製して残しておき、キーを押している間は実時間と同じ速さで古いものに戻る
+pub struct Rewind {
+    history: VecDeque<Vec<Game>>, // 後ろほど新しい
+    recordtimer: Timer,           // ゲーム内時間で測る。戻る速さが揃うよう余りは持ち越す
+    rewindtimer: Timer,           // 巻き戻し中の実時間で測る
+}
+
+impl Rewind {
+    pub fn new() -> Rewind {
+        Rewind {
+            history: VecDeque::new(),
+            recordtimer: Timer::with_mode(REWIND_INTERVAL, TimerMode::Carry),
+            rewindtimer: Timer::with_mode(REWIND_INTERVAL, TimerMode::Carry),
+        }
+    }
+
+    // 新しいゲームを始めたときに呼ぶ
+    pub fn clear(&mut self) {
+        self.history.clear();
+        self.recordtimer.reset();
+        self.rewindtimer.reset();
+    }
+
+    // ゲームをdt進めたあとに呼ぶ
+    pub fn record(&mut self, games: &[Game], dt: u32) {
+        self.rewindtimer.reset();
+        self.recordtimer.add(dt);
+        if self.recordtimer.is_reached() {
+            self.history.push_back(games.to_vec());
+            if self.history.len() > REWIND_STATES {
+                self.history.pop_front();
+            }
+        }
+    }
+
+    // 巻き戻しキーを押している間、ゲームを進める代わりに呼ぶ
+    // 一番古い状態まで戻ったらそこで止まる。戻したらtrueを返す
+    pub fn rewind(&mut self, games: &mut Vec<Game>, dt: u32) -> bool {
+        let mut rewound = false;
+        self.rewindtimer.add(dt);
+        while self.rewindtimer.is_reached() {
+            let mut state = match self.history.len() {
+                0 => break,
+                1 => {
+                    self.rewindtimer.reset();
+                    self.history[0].clone()
+                }
+                _ => self.history.pop_back().unwrap(),
+            };
+            // 残したときに鳴らした音をもう一度鳴らさない
+            for game in &mut state {
+                game.requested_sounds.clear();
+                game.requested_musics.clear();
+            }
+            *games = state;
+            rewound = true;
+        }
+        self.recordtimer.reset();
+        rewound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::update_games;
+    use crate::replay::Replay;
+
+    // 2秒巻き戻して同じ入力で進め直すと、巻き戻さなかったときと同じになる
+    #[test]
+    fn rewound_game_replays_identically() {
+        let replay = Replay::load("tests/replays/enemies.replay").unwrap();
+        let mut games = vec![Game::new(replay.rules, replay.seed)];
+        let mut rewind = Rewind::new();
+        let mut times = Vec::new(); // 各フレームを進める前のゲーム内時間
+        for frame in &replay.frames {
+            times.push(games[0].now);
+            update_games(&mut games, &frame.commands, frame.dt);
+            rewind.record(&games, frame.dt);
+        }
+        let (score, now) = (games[0].score, games[0].now);
+
+        assert!(rewind.rewind(&mut games, 2000));
+        assert!(games[0].now < now);
+        assert!(now - games[0].now <= 2000 + REWIND_INTERVAL as u32);
+
+        let resume = times.iter().position(|time| *time == games[0].now).unwrap();
+        for frame in &replay.frames[resume..] {
+            update_games(&mut games, &frame.commands, frame.dt);
+        }
+        assert_eq!(games[0].now, now);
+        assert_eq!(games[0].score, score);
+
+        // 残した数より長く押しても一番古い状態で止まる
+        rewind.rewind(&mut games, 1_000_000);
+        let oldest = games[0].now;
+        assert!(now - oldest >= (REWIND_STATES as u32 - 1) * REWIND_INTERVAL as u32);
+        rewind.rewind(&mut games, 1_000_000);
+        assert_eq!(games[0].now, oldest);
+    }
+}
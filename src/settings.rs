@@ -0,0 +1,276 @@
+use std::fs;
+use std::io;
+
+use crate::model::Rules;
+
+pub const SETTINGS_FILE: &str = "./settings.cfg";
+pub const VOLUME_STEPS: i32 = 10;
+pub const MAX_SCALE: u32 = 3;
+pub const FPS_LIMITS: [u32; 6] = [0, 30, 60, 120, 144, 240]; // 0は制限なし
+
+// 設定画面で選べるルールの組み合わせ
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RulePreset {
+    Classic,  // 元のDown!!と同じ
+    Standard, // 敵あり
+    Ceiling,  // 敵あり、天井の針あり
+}
+
+pub const RULE_PRESETS: [RulePreset; 3] = [
+    RulePreset::Classic,
+    RulePreset::Standard,
+    RulePreset::Ceiling,
+];
+
+impl RulePreset {
+    pub fn name(self) -> &'static str {
+        match self {
+            RulePreset::Classic => "classic",
+            RulePreset::Standard => "standard",
+            RulePreset::Ceiling => "ceiling",
+        }
+    }
+
+    pub fn apply(self, rules: &mut Rules) {
+        rules.enemies = self != RulePreset::Classic;
+        rules.ceiling = self == RulePreset::Ceiling;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub music_volume: i32, // 0..=VOLUME_STEPS
+    pub sound_volume: i32, // 0..=VOLUME_STEPS
+    pub scale: u32,        // 1..=MAX_SCALE
+    pub fullscreen: bool,
+    pub show_fps: bool,
+    pub vsync: bool,  // 次の起動から有効
+    pub max_fps: u32, // FPS_LIMITSのどれか
+    pub preset: RulePreset,
+    pub frequency: i32, // 以下は設定ファイルでのみ変更できる
+    pub chunk_size: i32,
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            music_volume: VOLUME_STEPS,
+            sound_volume: VOLUME_STEPS,
+            scale: 1,
+            fullscreen: false,
+            show_fps: true,
+            vsync: true,
+            max_fps: 0,
+            preset: RulePreset::Classic,
+            frequency: 22010, // デフォルトは22050
+            chunk_size: 1_024,
+        }
+    }
+
+    // 設定ファイルが無ければ初期設定のまま
+    //   music_volume = 8
+    pub fn load(path: &str) -> Settings {
+        let mut settings = Settings::new();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => return settings,
+        };
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => {
+                    println!("Invalid line in {}: {}", path, line);
+                    continue;
+                }
+            };
+            if !settings.set(name, value) {
+                println!("Invalid setting in {}: {}", path, line);
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let text = format!(
+            "music_volume = {}\n\
+             sound_volume = {}\n\
+             scale = {}\n\
+             fullscreen = {}\n\
+             show_fps = {}\n\
+             vsync = {}\n\
+             max_fps = {}\n\
+             rules = {}\n\
+             frequency = {}\n\
+             chunk_size = {}\n",
+            self.music_volume,
+            self.sound_volume,
+            self.scale,
+            self.fullscreen,
+            self.show_fps,
+            self.vsync,
+            self.max_fps,
+            self.preset.name(),
+            self.frequency,
+            self.chunk_size,
+        );
+        fs::write(path, text)
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> bool {
+        match name {
+            "music_volume" => match value.parse() {
+                Ok(v) if (0..=VOLUME_STEPS).contains(&v) => self.music_volume = v,
+                _ => return false,
+            },
+            "sound_volume" => match value.parse() {
+                Ok(v) if (0..=VOLUME_STEPS).contains(&v) => self.sound_volume = v,
+                _ => return false,
+            },
+            "scale" => match value.parse() {
+                Ok(v) if (1..=MAX_SCALE).contains(&v) => self.scale = v,
+                _ => return false,
+            },
+            "fullscreen" => match value.parse() {
+                Ok(v) => self.fullscreen = v,
+                _ => return false,
+            },
+            "show_fps" => match value.parse() {
+                Ok(v) => self.show_fps = v,
+                _ => return false,
+            },
+            "vsync" => match value.parse() {
+                Ok(v) => self.vsync = v,
+                _ => return false,
+            },
+            "max_fps" => match value.parse() {
+                Ok(v) if FPS_LIMITS.contains(&v) => self.max_fps = v,
+                _ => return false,
+            },
+            "rules" => match RULE_PRESETS.iter().find(|preset| preset.name() == value) {
+                Some(&preset) => self.preset = preset,
+                None => return false,
+            },
+            "frequency" => match value.parse() {
+                Ok(v) if v > 0 => self.frequency = v,
+                _ => return false,
+            },
+            "chunk_size" => match value.parse() {
+                Ok(v) if v > 0 => self.chunk_size = v,
+                _ => return false,
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    // SDL_mixerの音量(0..=128)
+    pub fn music_volume_sdl(&self) -> i32 {
+        self.music_volume * sdl2::mixer::MAX_VOLUME / VOLUME_STEPS
+    }
+
+    pub fn sound_volume_sdl(&self) -> i32 {
+        self.sound_volume * sdl2::mixer::MAX_VOLUME / VOLUME_STEPS
+    }
+}
+
+// 設定画面の項目
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Item {
+    MusicVolume,
+    SoundVolume,
+    Scale,
+    Fullscreen,
+    ShowFps,
+    Vsync,
+    MaxFps,
+    Rules,
+    KeyConfig,
+    Back,
+}
+
+pub const ITEMS: [Item; 10] = [
+    Item::MusicVolume,
+    Item::SoundVolume,
+    Item::Scale,
+    Item::Fullscreen,
+    Item::ShowFps,
+    Item::Vsync,
+    Item::MaxFps,
+    Item::Rules,
+    Item::KeyConfig,
+    Item::Back,
+];
+
+impl Item {
+    pub fn label(self) -> &'static str {
+        match self {
+            Item::MusicVolume => "MUSIC VOLUME",
+            Item::SoundVolume => "SE VOLUME",
+            Item::Scale => "WINDOW SCALE",
+            Item::Fullscreen => "FULLSCREEN",
+            Item::ShowFps => "SHOW FPS",
+            Item::Vsync => "VSYNC",
+            Item::MaxFps => "MAX FPS",
+            Item::Rules => "RULES",
+            Item::KeyConfig => "KEY CONFIG",
+            Item::Back => "BACK",
+        }
+    }
+
+    pub fn value(self, settings: &Settings) -> String {
+        let on_off = |b: bool| if b { "ON" } else { "OFF" }.to_string();
+        match self {
+            Item::MusicVolume => settings.music_volume.to_string(),
+            Item::SoundVolume => settings.sound_volume.to_string(),
+            Item::Scale => format!("x{}", settings.scale),
+            Item::Fullscreen => on_off(settings.fullscreen),
+            Item::ShowFps => on_off(settings.show_fps),
+            Item::Vsync => on_off(settings.vsync) + " (RESTART)",
+            Item::MaxFps => match settings.max_fps {
+                0 => "OFF".to_string(),
+                fps => fps.to_string(),
+            },
+            Item::Rules => settings.preset.name().to_uppercase(),
+            Item::KeyConfig | Item::Back => String::new(),
+        }
+    }
+
+    // 左右キーで値を変える。dirは-1か1
+    pub fn change(self, settings: &mut Settings, dir: i32) {
+        match self {
+            Item::MusicVolume => {
+                settings.music_volume = (settings.music_volume + dir).clamp(0, VOLUME_STEPS)
+            }
+            Item::SoundVolume => {
+                settings.sound_volume = (settings.sound_volume + dir).clamp(0, VOLUME_STEPS)
+            }
+            Item::Scale => {
+                settings.scale = (settings.scale as i32 + dir).clamp(1, MAX_SCALE as i32) as u32
+            }
+            Item::Fullscreen => settings.fullscreen = !settings.fullscreen,
+            Item::ShowFps => settings.show_fps = !settings.show_fps,
+            Item::Vsync => settings.vsync = !settings.vsync,
+            Item::MaxFps => {
+                let n = FPS_LIMITS.len() as i32;
+                let i = FPS_LIMITS
+                    .iter()
+                    .position(|fps| *fps == settings.max_fps)
+                    .unwrap() as i32;
+                settings.max_fps = FPS_LIMITS[((i + dir + n) % n) as usize];
+            }
+            Item::Rules => {
+                let n = RULE_PRESETS.len() as i32;
+                let i = RULE_PRESETS
+                    .iter()
+                    .position(|preset| *preset == settings.preset)
+                    .unwrap() as i32;
+                settings.preset = RULE_PRESETS[((i + dir + n) % n) as usize];
+            }
+            Item::KeyConfig | Item::Back => {}
+        }
+    }
+}
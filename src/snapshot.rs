@@ -0,0 +1,485 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::course::Course;
+use crate::floor::Floors;
+use crate::mode::Mode;
+use crate::model::*;
+
+pub const SNAPSHOT_FILE: &str = "./quick.snapshot";
+pub const SUSPEND_FILE: &str = "./suspend.snapshot";
+
+// 途中のGameの状態をすべて書き出し、そのまま再開できるようにする
+// 1行に1項目を決まった順で並べる。音の要求のように次のフレームで消えるものは保存しない
+//   games = 1
+//   versus = false
+//   rules = true false 18 30 1
+//   rng = <種(16進)> <使った語数>
+//   ...
+// versusは勝ち負けの決め方で、Gameの外で持っているので一緒に書く
+pub struct Snapshot {
+    pub games: Vec<Game>,
+    pub versus: bool,
+}
+
+impl Snapshot {
+    // 同じところから何度でもやり直せるので、記録には残さない
+    pub fn unrank(&mut self) {
+        for game in &mut self.games {
+            game.ranked = false;
+        }
+    }
+}
+
+pub fn save(games: &[Game], versus: bool, path: &str) -> io::Result<()> {
+    let mut w = Writer::default();
+    w.line("games", games.len());
+    w.line("versus", versus);
+    for game in games {
+        write_game(&mut w, game);
+    }
+    fs::write(path, w.text)
+}
+
+pub fn load(path: &str) -> Result<Snapshot, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut r = Reader {
+        lines: text.lines(),
+        line: 0,
+    };
+    read_snapshot(&mut r).map_err(|e| format!("{}:{}: {}", path, r.line, e))
+}
+
+fn read_snapshot(r: &mut Reader) -> Result<Snapshot, String> {
+    let n: usize = r.value("games")?;
+    if n == 0 || n > MAX_PLAYERS {
+        return Err("invalid number of games".to_string());
+    }
+    let versus = r.value("versus")?;
+    let games = (0..n)
+        .map(|_| read_game(r))
+        .collect::<Result<Vec<Game>, String>>()?;
+    if games.iter().map(|game| game.rules.players).sum::<usize>() > MAX_PLAYERS {
+        return Err("too many players".to_string());
+    }
+    Ok(Snapshot { games, versus })
+}
+
+fn write_game(w: &mut Writer, game: &Game) {
+    let rules = &game.rules;
+    w.line(
+        "rules",
+        format!(
+            "{} {} {} {} {}",
+            rules.enemies, rules.ceiling, rules.wid, rules.hei, rules.players
+        ),
+    );
+    let seed: String = game
+        .rng
+        .get_seed()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    w.line("rng", format!("{} {}", seed, game.rng.get_word_pos()));
+    w.line("is_over", game.is_over);
+    w.line("isfloor", game.isfloor);
+    w.line("score", game.score);
+    w.line("now", game.now);
+    w.line("highscore", join(&game.highscore));
+    w.line("ranked", game.ranked);
+    w.line("mode", game.mode.name());
+    w.line("cleartime", optional(game.cleartime));
+    w.line("floors", game.floors.name());
+    // コースは中身でなくファイルの場所とidを書き、読むときに読み直す
+    w.line(
+        "course",
+        optional(game.course.as_ref().map(|course| &course.path)),
+    );
+    w.line(
+        "course_id",
+        optional(game.course.as_ref().map(|course| &course.id)),
+    );
+    w.timer("falltimer", &game.falltimer);
+    w.line(
+        "system",
+        format!(
+            "{} {} {}",
+            game.system.time, game.system.count, game.system.fps
+        ),
+    );
+    for row in &game.data {
+        w.line("row", row.iter().map(|c| c.to_char()).collect::<String>());
+    }
+
+    w.line("hitos", game.hitos.len());
+    for hito in &game.hitos {
+        w.line(
+            "hito",
+            format!(
+                "{} {} {} {} {} {} {} {} {} {} {} {}",
+                hito.x,
+                hito.y,
+                hito.hitonum,
+                hito.hide,
+                hito.muteki,
+                hito.para,
+                hito.omori,
+                hito.flashing,
+                hito.dead,
+                hito.deadtime,
+                hito.life,
+                hito.mutekistart
+            ),
+        );
+        w.line(
+            "gauge",
+            format!(
+                "{} {} {}",
+                hito.gauge.damaging, hito.gauge.flashing, hito.gauge.is_red
+            ),
+        );
+        w.timer("damagetimer", &hito.gauge.damagetimer);
+        w.timer("gaugeflashtimer", &hito.gauge.flashtimer);
+        w.timer("walktimer", &hito.walktimer);
+        w.timer("flashtimer", &hito.flashtimer);
+        w.timer("wavetimer", &hito.wavetimer);
+        w.timer("mutekiflashtimer", &hito.mutekiflashtimer);
+        w.timer("haribreaktimer", &hito.haribreaktimer);
+        w.timer("gameovertimer", &hito.gameovertimer);
+    }
+
+    w.line("enemies", game.enemies.len());
+    for enemy in &game.enemies {
+        w.line(
+            "enemy",
+            format!(
+                "{} {} {} {} {}",
+                enemy.x, enemy.y, enemy.dir, enemy.anime, enemy.dead
+            ),
+        );
+        w.timer("walktimer", &enemy.walktimer);
+    }
+
+    w.line("effects", game.effects.len());
+    for effect in &game.effects {
+        let name = match effect._type {
+            EffectType::BREAK => "break",
+            EffectType::PANG => "pang",
+            EffectType::CRUSH => "crush",
+        };
+        w.line(
+            "effect",
+            format!(
+                "{} {} {} {} {}",
+                effect.x, effect.y, name, effect.state, effect.dead
+            ),
+        );
+        w.timer("timer", &effect.timer);
+    }
+}
+
+fn read_game(r: &mut Reader) -> Result<Game, String> {
+    let v = r.values("rules", 5)?;
+    let rules = Rules {
+        enemies: parse(v[0])?,
+        ceiling: parse(v[1])?,
+        wid: parse(v[2])?,
+        hei: parse(v[3])?,
+        players: parse(v[4])?,
+    };
+    if !rules.is_valid() {
+        return Err("invalid rules".to_string());
+    }
+    let v = r.values("rng", 2)?;
+    if v[0].len() != 64 {
+        return Err("invalid rng seed".to_string());
+    }
+    let mut seed = [0u8; 32];
+    for (i, byte) in seed.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&v[0][i * 2..i * 2 + 2], 16)
+            .map_err(|_| "invalid rng seed".to_string())?;
+    }
+    // 最初の床を作るのに乱数を使うので、作った後で位置を戻す
+    let mut game = Game::with_rng(rules, ChaCha12Rng::from_seed(seed));
+    game.rng.set_word_pos(parse(v[1])?);
+
+    game.is_over = r.value("is_over")?;
+    game.isfloor = r.value("isfloor")?;
+    game.score = r.value("score")?;
+    game.now = r.value("now")?;
+    game.highscore = r
+        .values("highscore", 0)?
+        .into_iter()
+        .map(parse)
+        .collect::<Result<Vec<i32>, String>>()?;
+    game.ranked = r.value("ranked")?;
+    let mode = r.values("mode", 1)?[0];
+    game.mode = Mode::from_name(mode).ok_or(format!("unknown mode: {}", mode))?;
+    game.cleartime = r.optional("cleartime")?;
+    let floors = r.values("floors", 1)?[0];
+    game.floors = Floors::from_name(floors).ok_or(format!("unknown floors: {}", floors))?;
+    // 場所やidに空白が入っていてもよいよう、値を区切らずに読む
+    let path = r.raw("course")?;
+    let id = r.raw("course_id")?;
+    if !path.is_empty() {
+        let course = Course::load(path)?;
+        if id != course.id {
+            return Err(format!("{} has changed", path));
+        }
+        if course.wid != game.wid {
+            return Err(format!("{}: the width differs from the game", path));
+        }
+        game.course = Some(Rc::new(course));
+    }
+    game.falltimer = r.timer("falltimer")?;
+    let v = r.values("system", 3)?;
+    game.system = System {
+        time: parse(v[0])?,
+        count: parse(v[1])?,
+        fps: parse(v[2])?,
+    };
+    for y in 0..game.hei as usize {
+        let row = r.values("row", 1)?[0];
+        if row.chars().count() != game.wid as usize {
+            return Err("invalid row width".to_string());
+        }
+        for (x, c) in row.chars().enumerate() {
+            game.data[y][x] = Chara::from_char(c).ok_or(format!("invalid cell: {}", c))?;
+        }
+    }
+
+    let n: usize = r.value("hitos")?;
+    game.hitos = Vec::new();
+    for _ in 0..n {
+        let v = r.values("hito", 12)?;
+        let mut hito = Hito::new(parse(v[0])?, parse(v[1])?);
+        hito.hitonum = parse(v[2])?;
+        hito.hide = parse(v[3])?;
+        hito.muteki = parse(v[4])?;
+        hito.para = parse(v[5])?;
+        hito.omori = parse(v[6])?;
+        hito.flashing = parse(v[7])?;
+        hito.dead = parse(v[8])?;
+        hito.deadtime = parse(v[9])?;
+        hito.life = parse(v[10])?;
+        hito.mutekistart = parse(v[11])?;
+        let v = r.values("gauge", 3)?;
+        hito.gauge.damaging = parse(v[0])?;
+        hito.gauge.flashing = parse(v[1])?;
+        hito.gauge.is_red = parse(v[2])?;
+        hito.gauge.damagetimer = r.timer("damagetimer")?;
+        hito.gauge.flashtimer = r.timer("gaugeflashtimer")?;
+        hito.walktimer = r.timer("walktimer")?;
+        hito.flashtimer = r.timer("flashtimer")?;
+        hito.wavetimer = r.timer("wavetimer")?;
+        hito.mutekiflashtimer = r.timer("mutekiflashtimer")?;
+        hito.haribreaktimer = r.timer("haribreaktimer")?;
+        hito.gameovertimer = r.timer("gameovertimer")?;
+        if !(0..game.wid).contains(&hito.x) || !(0..game.hei - 1).contains(&hito.y) {
+            return Err("player out of the field".to_string());
+        }
+        game.hitos.push(hito);
+    }
+    if game.hitos.len() != rules.players {
+        return Err("wrong number of players".to_string());
+    }
+
+    let n: usize = r.value("enemies")?;
+    for _ in 0..n {
+        let v = r.values("enemy", 5)?;
+        let mut enemy = Enemy::new(parse(v[0])?, parse(v[1])?, parse(v[2])?);
+        enemy.anime = parse(v[3])?;
+        enemy.dead = parse(v[4])?;
+        enemy.walktimer = r.timer("walktimer")?;
+        if !(0..game.wid).contains(&enemy.x) || !(0..game.hei - 1).contains(&enemy.y) {
+            return Err("enemy out of the field".to_string());
+        }
+        game.enemies.push(enemy);
+    }
+
+    let n: usize = r.value("effects")?;
+    for _ in 0..n {
+        let v = r.values("effect", 5)?;
+        let _type = match v[2] {
+            "break" => EffectType::BREAK,
+            "pang" => EffectType::PANG,
+            "crush" => EffectType::CRUSH,
+            _ => return Err(format!("unknown effect: {}", v[2])),
+        };
+        let timer = r.timer("timer")?;
+        let mut effect = Effect::new(parse(v[0])?, parse(v[1])?, _type, timer);
+        effect.state = parse(v[3])?;
+        effect.dead = parse(v[4])?;
+        if !(0..game.wid).contains(&effect.x) || !(0..game.hei).contains(&effect.y) {
+            return Err("effect out of the field".to_string());
+        }
+        game.effects.push(effect);
+    }
+
+    Ok(game)
+}
+
+fn join(values: &[i32]) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// Noneは値を空にする
+fn optional<T: Display>(value: Option<T>) -> String {
+    value.map_or(String::new(), |value| value.to_string())
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value: {}", value))
+}
+
+#[derive(Default)]
+struct Writer {
+    text: String,
+}
+
+impl Writer {
+    fn line(&mut self, name: &str, value: impl Display) {
+        let line = format!("{} = {}", name, value);
+        self.text += line.trim_end();
+        self.text += "\n";
+    }
+
+    fn timer(&mut self, name: &str, timer: &Timer) {
+        let (waittime, wait, mode) = timer.state();
+        let mode = match mode {
+            TimerMode::Reset => "reset",
+            TimerMode::Carry => "carry",
+        };
+        self.line(name, format!("{} {} {}", waittime, wait, mode));
+    }
+}
+
+struct Reader<'a> {
+    lines: std::str::Lines<'a>,
+    line: usize, // エラー表示用の行番号
+}
+
+impl<'a> Reader<'a> {
+    // 次の行が name であることを確かめて値を空白で区切って返す。countが0なら個数は問わない
+    fn values(&mut self, name: &str, count: usize) -> Result<Vec<&'a str>, String> {
+        let values: Vec<&str> = self.raw(name)?.split_whitespace().collect();
+        if count > 0 && values.len() != count {
+            return Err(format!("{} needs {} values", name, count));
+        }
+        Ok(values)
+    }
+
+    // 次の行が name であることを確かめて、=の後ろを区切らずに返す
+    fn raw(&mut self, name: &str) -> Result<&'a str, String> {
+        let line = self.lines.next().ok_or(format!("{} is missing", name))?;
+        self.line += 1;
+        let (key, value) = line
+            .split_once('=')
+            .ok_or(format!("invalid line: {}", line))?;
+        if key.trim() != name {
+            return Err(format!("expected {}, found {}", name, key.trim()));
+        }
+        Ok(value.trim())
+    }
+
+    fn value<T: FromStr>(&mut self, name: &str) -> Result<T, String> {
+        parse(self.values(name, 1)?[0])
+    }
+
+    fn optional<T: FromStr>(&mut self, name: &str) -> Result<Option<T>, String> {
+        match self.values(name, 0)?[..] {
+            [] => Ok(None),
+            [value] => parse(value).map(Some),
+            _ => Err(format!("{} needs 0 or 1 value", name)),
+        }
+    }
+
+    fn timer(&mut self, name: &str) -> Result<Timer, String> {
+        let v = self.values(name, 3)?;
+        let waittime: i32 = parse(v[0])?;
+        if waittime <= 0 {
+            return Err(format!("invalid timer: {}", name));
+        }
+        let mode = match v[2] {
+            "reset" => TimerMode::Reset,
+            "carry" => TimerMode::Carry,
+            _ => return Err(format!("invalid timer mode: {}", v[2])),
+        };
+        Ok(Timer::from_state(waittime, parse(v[1])?, mode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::Replay;
+
+    // リプレイを途中まで進めて保存し、読み込んだものと元のものを同じ入力で進めて比べる
+    #[test]
+    fn restored_game_continues_identically() {
+        let replay = Replay::load("tests/replays/enemies.replay").unwrap();
+        let mut games = vec![Game::new(replay.rules, replay.seed)];
+        let (first, rest) = replay.frames.split_at(replay.frames.len() / 2);
+        for frame in first {
+            update_games(&mut games, &frame.commands, frame.dt);
+        }
+
+        let path = std::env::temp_dir().join("rust-down-test.snapshot");
+        let path = path.to_str().unwrap();
+        save(&games, true, path).unwrap();
+        let snapshot = load(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert!(snapshot.versus);
+        let mut restored = snapshot.games;
+
+        for game in games.iter_mut().chain(restored.iter_mut()) {
+            game.field_log = Some(Vec::new());
+        }
+        for frame in rest {
+            update_games(&mut games, &frame.commands, frame.dt);
+            update_games(&mut restored, &frame.commands, frame.dt);
+        }
+        let (game, restored) = (&games[0], &restored[0]);
+        assert!(!game.field_log.as_ref().unwrap().is_empty());
+        assert_eq!(game.field_log, restored.field_log);
+        assert_eq!(game.score, restored.score);
+        assert_eq!(game.now, restored.now);
+        assert_eq!(game.hitos[0].life, restored.hitos[0].life);
+        assert_eq!(game.hitos[0].deadtime, restored.hitos[0].deadtime);
+    }
+
+    // コースの場所に空白が入っていても読める
+    #[test]
+    fn course_path_may_contain_spaces() {
+        let dir = std::env::temp_dir();
+        let course_path = dir.join("rust down test.course");
+        let course_path = course_path.to_str().unwrap();
+        fs::copy("courses/tutorial.course", course_path).unwrap();
+        let course = Course::load(course_path).unwrap();
+        let mut rules = Rules::new();
+        rules.wid = course.wid;
+        let mut game = Game::new(rules, 5);
+        game.mode = Mode::Course;
+        game.set_course(Rc::new(course));
+
+        let path = dir.join("rust-down-test-course.snapshot");
+        let path = path.to_str().unwrap();
+        save(&[game], false, path).unwrap();
+        let snapshot = load(path);
+        fs::remove_file(path).unwrap();
+        fs::remove_file(course_path).unwrap();
+        let course = snapshot.unwrap().games[0].course.clone().unwrap();
+        assert_eq!(course.path, course_path);
+    }
+}